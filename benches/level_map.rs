@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use combined_ob::{market_data::LevelMap, msg};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rust_decimal::Decimal;
+
+const EXCHANGES: [&str; 5] = ["binance", "bitstamp", "kraken", "coinbase", "bybit"];
+const DEPTH: i64 = 1000;
+const INSTRUMENT: &str = "BTC/USDT";
+
+/// `DEPTH` levels a side around 20000, one cent apart, shifted by `offset`
+/// cents so each exchange and each update quotes different prices.
+fn levels(exchange: &'static str, offset: i64) -> msg::Levels {
+    let level = |cents: i64| msg::Level {
+        exchange,
+        price: Decimal::new(2_000_000 + cents + offset, 2),
+        amount: Decimal::new(15, 1),
+        synthetic: false,
+    };
+
+    msg::Levels {
+        exchange,
+        instrument: INSTRUMENT,
+        bids: (1..=DEPTH).map(|i| level(-i)).collect(),
+        asks: (1..=DEPTH).map(level).collect(),
+    }
+}
+
+fn full_map() -> LevelMap {
+    let mut map = LevelMap::new(INSTRUMENT, Duration::from_secs(60));
+    for (i, exchange) in EXCHANGES.into_iter().enumerate() {
+        map.update(levels(exchange, i as i64), false);
+    }
+    map
+}
+
+/// The levels of `levels(exchange, 0)` with the amounts of the top `changed`
+/// levels a side changed, as most updates of a partial book are.
+fn changed_levels(exchange: &'static str, changed: usize, round: u32) -> msg::Levels {
+    let mut levels = levels(exchange, 0);
+    for level in levels
+        .bids
+        .iter_mut()
+        .take(changed)
+        .chain(levels.asks.iter_mut().take(changed))
+    {
+        level.amount += Decimal::new(round as i64 % 2 + 1, 2);
+    }
+    levels
+}
+
+fn level_map(c: &mut Criterion) {
+    let mut map = full_map();
+    let mut offset = 0;
+    c.bench_function("update, book shifted a few ticks", |b| {
+        b.iter_batched(
+            || {
+                offset = (offset + 1) % 10;
+                levels(EXCHANGES[0], offset)
+            },
+            |levels| map.update(levels, false),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let mut map = full_map();
+    map.update(levels(EXCHANGES[0], 0), false);
+    let mut round = 0;
+    c.bench_function("update, 10 levels changed", |b| {
+        b.iter_batched(
+            || {
+                round += 1;
+                changed_levels(EXCHANGES[0], 10, round)
+            },
+            |levels| map.update(levels, false),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let mut map = full_map();
+    map.update(levels(EXCHANGES[0], 0), false);
+    let mut round = 0;
+    c.bench_function("update and book, 10 levels changed", |b| {
+        b.iter_batched(
+            || {
+                round += 1;
+                changed_levels(EXCHANGES[0], 10, round)
+            },
+            |levels| {
+                map.update(levels, false);
+                map.book()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let map = full_map();
+    c.bench_function("book", |b| b.iter(|| map.book()));
+}
+
+criterion_group!(benches, level_map);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::SETTINGS;
+
+/// Exponential backoff with jitter between reconnect attempts.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff {
+            min: Duration::from_millis(SETTINGS.reconnect.min_backoff_ms),
+            max: Duration::from_millis(SETTINGS.reconnect.max_backoff_ms),
+            attempt: 0,
+        }
+    }
+
+    /// Returns the delay before the next attempt, picked at random from the
+    /// upper half of the current exponential step so venues don't reconnect in lockstep.
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self
+            .min
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = step / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
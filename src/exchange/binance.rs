@@ -1,129 +1,330 @@
-use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use futures_util::FutureExt;
 use serde::{Deserialize, Serialize};
-use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use serde_json::Value;
+use tokio::{task::JoinHandle, time};
+use tokio_tungstenite::tungstenite::Error;
 
 use crate::{
-    exchange::util::{read_from_stream, LoopState},
-    msg::{Level, Levels},
-    shutdown, SETTINGS,
+    exchange::{
+        backoff::Backoff,
+        book::LocalBook,
+        util::{
+            connect, fetch_json, next_text, parse_levels, parse_text, send_json, symbol_map,
+            LoopState, ReadStream, WriteSink, WsStream,
+        },
+        Connector, Resync,
+    },
+    msg::Levels,
+    settings::BookMode,
+    SETTINGS,
 };
 
-type WriteSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
-
 pub struct Binance {
-    pub levels_tx: mpsc::Sender<Levels>,
-    pub shutdown_rx: shutdown::Receiver,
+    request_id: usize,
+    // Instrument of every subscribed stream
+    streams: HashMap<String, &'static str>,
+    books: HashMap<&'static str, DiffBook>,
+}
+
+/// An instrument's local book, built following Binance's recipe: buffer the
+/// diffs, fetch a snapshot, drop the buffered diffs it already contains and
+/// apply the rest. Instruments sync on their own, a gap only rebuilding the
+/// book it was found in.
+struct DiffBook {
+    book: LocalBook,
+    last_update_id: u64,
+    // Diffs received since the book started syncing, until it synced
+    buffer: Vec<DepthUpdate>,
+    // Checked whenever a diff arrives, so a quiet instrument shows up with its
+    // next diff after the snapshot was fetched
+    snapshot: Option<JoinHandle<Result<PartialBookDepth, reqwest::Error>>>,
+    backoff: Backoff,
 }
 
 const EXCHANGE: &str = "Binance";
-const BINANCE_WSS: &str = "wss://stream.binance.com:9443/ws";
+// Combined streams wrap every message with the name of the stream it belongs to
+const BINANCE_WSS: &str = "wss://stream.binance.com:9443/stream";
+const BINANCE_DEPTH_URL: &str = "https://api.binance.com/api/v3/depth";
 
 impl Binance {
-    pub async fn connect(&mut self) {
-        let url = url::Url::parse(BINANCE_WSS).unwrap();
-        let (ws_stream, _) = connect_async(url)
-            .await
-            .expect("Failed to connect to Binance");
+    pub fn new() -> Binance {
+        Binance {
+            request_id: 0,
+            streams: symbol_map(SETTINGS.instruments(EXCHANGE), Self::stream_name),
+            books: HashMap::new(),
+        }
+    }
 
-        let (mut write, mut read) = ws_stream.split();
+    fn next_request_id(&mut self) -> usize {
+        self.request_id += 1;
+        self.request_id
+    }
 
-        Self::subscribe_to_partial_book_depth(&mut write, 1).await;
-        let res = read_from_stream::<Response>(&mut read).await.unwrap();
-        assert_eq!(None, res.result);
-        assert_eq!(1, res.id);
+    /// Maps a `BASE/QUOTE` pair to Binance's `BASEQUOTE` symbol.
+    fn symbol(instrument: &str) -> String {
+        instrument.to_uppercase().replace('/', "")
+    }
 
+    fn stream_name(instrument: &str) -> String {
+        let symbol = Self::symbol(instrument).to_lowercase();
+        match SETTINGS.binance.mode {
+            BookMode::Partial => format!(
+                "{}@depth{}@{}",
+                symbol, SETTINGS.binance.depth, SETTINGS.binance.latency
+            ),
+            BookMode::Diff => format!("{}@depth@{}", symbol, SETTINGS.binance.latency),
+        }
+    }
+
+    fn request(&mut self, method: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            params: self.streams.keys().cloned().collect(),
+            id: self.next_request_id(),
+        }
+    }
+
+    /// Waits for the response to request `id`, skipping anything sent before it.
+    async fn await_response(read: &mut ReadStream, id: usize) -> Result<(), LoopState> {
         loop {
-            tokio::select! {
-                res = read_from_stream::<PartialBookDepth>(&mut read) => {
-                    match res {
-                        Ok(data) => {
-                            self.process_partial_book_depth(data).await;
-                        },
-                        Err(state) => {
-                            if let LoopState::Break = state {
-                                break;
-                            }
-                        },
-                    }
-                },
-                _ = self.shutdown_rx.recv() => {
-                    break;
+            let text = next_text(read).await?;
+            if let Ok(res) = serde_json::from_str::<Response>(&text) {
+                if res.id != id {
+                    continue;
+                }
+                if res.result.is_some() || res.error.is_some() {
+                    eprintln!("Unexpected response from Binance: {}", text);
+                    return Err(LoopState::Break);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl DiffBook {
+    fn new() -> DiffBook {
+        DiffBook {
+            book: LocalBook::default(),
+            last_update_id: 0,
+            buffer: Vec::new(),
+            snapshot: None,
+            backoff: Backoff::new(),
+        }
+    }
+
+    /// Applies a diff once the book synced and buffers it until then,
+    /// returning whether the book changed.
+    fn receive(&mut self, instrument: &'static str, update: DepthUpdate) -> bool {
+        if self.book.is_synced() {
+            return match self.apply(instrument, &update) {
+                Ok(applied) => applied,
+                Err(Resync) => {
+                    self.book = LocalBook::default();
+                    self.buffer.push(update);
+                    self.fetch(instrument, Duration::ZERO);
+                    // The cleared book takes the instrument out of the summary until it synced again
+                    true
                 }
             };
         }
 
-        Self::unsubscribe_from_partial_book_depth(&mut write, 2).await;
-
-        let _ = write.close();
-
-        println!("Exiting binance...");
-    }
-
-    async fn process_partial_book_depth(&self, book: PartialBookDepth) {
-        let bids: Vec<_> = book
-            .bids
-            .into_iter()
-            .map(|b| Level {
-                exchange: EXCHANGE,
-                price: b[0].parse().unwrap(),
-                amount: b[1].parse().unwrap(),
-            })
-            .collect();
-
-        let asks: Vec<_> = book
-            .asks
-            .into_iter()
-            .map(|b| Level {
-                exchange: EXCHANGE,
-                price: b[0].parse().unwrap(),
-                amount: b[1].parse().unwrap(),
-            })
-            .collect();
-
-        let levels = Levels {
-            exchange: EXCHANGE,
-            bids,
-            asks,
+        self.buffer.push(update);
+        let res = match self.snapshot.as_mut() {
+            Some(fetch) => match fetch.now_or_never() {
+                Some(res) => res,
+                None => return false,
+            },
+            None => {
+                self.fetch(instrument, Duration::ZERO);
+                return false;
+            }
         };
+        self.snapshot = None;
+
+        let snapshot = match res {
+            Ok(Ok(snapshot)) => snapshot,
+            Ok(Err(err)) => {
+                eprintln!("Could not fetch Binance {} snapshot: {}", instrument, err);
+                self.refetch(instrument);
+                return false;
+            }
+            Err(err) => {
+                eprintln!("Binance {} snapshot fetch failed: {}", instrument, err);
+                self.refetch(instrument);
+                return false;
+            }
+        };
+
+        if snapshot.lastUpdateId + 1 < self.buffer[0].first_update_id {
+            eprintln!(
+                "Binance {} snapshot predates the buffered diffs, fetching another",
+                instrument
+            );
+            self.refetch(instrument);
+            return false;
+        }
 
-        if let Err(err) = self.levels_tx.send(levels).await {
-            eprintln!("Error sending message: {}", err);
+        self.book.snapshot();
+        self.book.update_bids(&snapshot.bids);
+        self.book.update_asks(&snapshot.asks);
+        self.last_update_id = snapshot.lastUpdateId;
+        self.backoff.reset();
+
+        let buffer = std::mem::take(&mut self.buffer);
+        for (i, update) in buffer.iter().enumerate() {
+            if self.apply(instrument, update).is_err() {
+                // Start over, keeping the diffs the next snapshot may need
+                self.book = LocalBook::default();
+                self.buffer = buffer.into_iter().skip(i).collect();
+                self.refetch(instrument);
+                return false;
+            }
         }
+
+        true
     }
 
-    async fn subscribe_to_partial_book_depth(write: &mut WriteSink, id: usize) {
-        let request = Request {
-            method: String::from("SUBSCRIBE"),
-            params: vec![format!(
-                "{}@depth{}@{}",
-                SETTINGS.binance.currency_pair, SETTINGS.binance.depth, SETTINGS.binance.latency
-            )],
-            id,
-        };
+    /// Fetches another snapshot once the backoff elapsed.
+    fn refetch(&mut self, instrument: &'static str) {
+        let delay = self.backoff.next_delay();
+        self.fetch(instrument, delay);
+    }
+
+    /// Fetches a snapshot after `delay`, the diffs received meanwhile being buffered.
+    fn fetch(&mut self, instrument: &'static str, delay: Duration) {
+        let url = format!(
+            "{}?symbol={}&limit={}",
+            BINANCE_DEPTH_URL,
+            Binance::symbol(instrument),
+            SETTINGS.binance.snapshot_limit
+        );
+
+        self.snapshot = Some(tokio::spawn(async move {
+            time::sleep(delay).await;
+            fetch_json::<PartialBookDepth>(&url).await
+        }));
+    }
+
+    /// Applies a diff to the synced book, returning `false` if the book
+    /// already contains it.
+    fn apply(&mut self, instrument: &'static str, update: &DepthUpdate) -> Result<bool, Resync> {
+        if update.final_update_id <= self.last_update_id {
+            return Ok(false);
+        }
+
+        if update.first_update_id > self.last_update_id + 1 {
+            eprintln!(
+                "Binance {} depth gap: expected update {}, got {}",
+                instrument,
+                self.last_update_id + 1,
+                update.first_update_id
+            );
+            return Err(Resync);
+        }
+
+        self.book.update_bids(&update.bids);
+        self.book.update_asks(&update.asks);
+        self.last_update_id = update.final_update_id;
+
+        Ok(true)
+    }
+}
 
-        if let Err(err) = write
-            .send(Message::Text(serde_json::to_string(&request).unwrap()))
-            .await
-        {
+impl Drop for DiffBook {
+    fn drop(&mut self) {
+        if let Some(fetch) = &self.snapshot {
+            fetch.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for Binance {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(BINANCE_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        let request = self.request("SUBSCRIBE");
+        let id = request.id;
+
+        if let Err(err) = send_json(write, &request).await {
             eprintln!("Could not subscribe to Binance: {}", err);
+            return Err(LoopState::Break);
+        }
+
+        Self::await_response(read, id).await?;
+
+        if let BookMode::Diff = SETTINGS.binance.mode {
+            self.books = self
+                .streams
+                .values()
+                .map(|&instrument| (instrument, DiffBook::new()))
+                .collect();
         }
+
+        Ok(())
     }
 
-    async fn unsubscribe_from_partial_book_depth(write: &mut WriteSink, id: usize) {
-        let request = Request {
-            method: String::from("UNSUBSCRIBE"),
-            params: vec![format!(
-                "{}@depth{}@{}",
-                SETTINGS.binance.currency_pair, SETTINGS.binance.depth, SETTINGS.binance.latency
-            )],
-            id,
-        };
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        match SETTINGS.binance.mode {
+            BookMode::Partial => {
+                let book = match parse_text::<Stream<PartialBookDepth>>(text) {
+                    Ok(book) => book,
+                    Err(_) => return Ok(None),
+                };
+                let instrument = match self.streams.get(&book.stream) {
+                    Some(&instrument) => instrument,
+                    None => return Ok(None),
+                };
+
+                Ok(Some(Levels {
+                    exchange: EXCHANGE,
+                    instrument,
+                    bids: parse_levels(EXCHANGE, book.data.bids),
+                    asks: parse_levels(EXCHANGE, book.data.asks),
+                }))
+            }
+            BookMode::Diff => {
+                let update = match parse_text::<Stream<DepthUpdate>>(text) {
+                    Ok(update) => update,
+                    Err(_) => return Ok(None),
+                };
+                let instrument = match self.streams.get(&update.stream) {
+                    Some(&instrument) => instrument,
+                    None => return Ok(None),
+                };
+
+                let book = match self.books.get_mut(instrument) {
+                    Some(book) => book,
+                    None => return Ok(None),
+                };
+
+                if book.receive(instrument, update.data) {
+                    Ok(Some(book.book.levels(EXCHANGE, instrument)))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
 
-        if let Err(err) = write
-            .send(Message::Text(serde_json::to_string(&request).unwrap()))
-            .await
-        {
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        let request = self.request("UNSUBSCRIBE");
+
+        if let Err(err) = send_json(write, &request).await {
             eprintln!("Could not unsubscribe from Binance: {}", err);
         }
     }
@@ -138,14 +339,34 @@ struct Request {
 
 #[derive(Deserialize)]
 struct Response {
-    result: Option<String>,
+    result: Option<Value>,
+    error: Option<Value>,
     id: usize,
 }
 
-#[allow(dead_code, non_snake_case)]
+#[derive(Deserialize)]
+struct Stream<T> {
+    stream: String,
+    data: T,
+}
+
+/// Both the partial depth stream and the REST depth snapshot.
+#[allow(non_snake_case)]
 #[derive(Deserialize)]
 struct PartialBookDepth {
-    lastUpdateId: u128,
+    lastUpdateId: u64,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+#[derive(Deserialize)]
+struct DepthUpdate {
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    #[serde(rename = "b")]
     bids: Vec<[String; 2]>,
+    #[serde(rename = "a")]
     asks: Vec<[String; 2]>,
 }
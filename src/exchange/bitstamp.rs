@@ -1,125 +1,242 @@
-use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use std::collections::HashMap;
+
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::Error;
 
 use crate::{
-    exchange::util::{read_from_stream, LoopState},
-    msg::{Level, Levels},
-    shutdown, SETTINGS,
+    exchange::{
+        book::LocalBook,
+        util::{
+            connect, fetch_json, next_text, parse_levels, parse_text, send_json, symbol_map,
+            LoopState, ReadStream, WriteSink, WsStream,
+        },
+        Connector, Resync,
+    },
+    msg::Levels,
+    settings::BookMode,
+    SETTINGS,
 };
 
-type WriteSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
-
 pub struct Bitstamp {
-    pub levels_tx: mpsc::Sender<Levels>,
-    pub shutdown_rx: shutdown::Receiver,
+    // Instrument of every subscribed channel
+    channels: HashMap<String, &'static str>,
+    books: HashMap<&'static str, DiffBook>,
+}
+
+#[derive(Default)]
+struct DiffBook {
+    book: LocalBook,
+    microtimestamp: u64,
 }
 
 impl Bitstamp {
     const EXCHANGE: &'static str = "Bitstamp";
     const BITSTAMP_WSS: &'static str = "wss://ws.bitstamp.net";
+    const BITSTAMP_ORDER_BOOK_URL: &'static str = "https://www.bitstamp.net/api/v2/order_book";
 
-    pub async fn connect(&mut self) {
-        let url = url::Url::parse(Self::BITSTAMP_WSS).unwrap();
-        let (ws_stream, _) = connect_async(url)
-            .await
-            .expect("Failed to connect to Bitstamp");
+    pub fn new() -> Bitstamp {
+        Bitstamp {
+            channels: symbol_map(
+                SETTINGS.instruments(Self::EXCHANGE),
+                Self::orderbook_channel,
+            ),
+            books: HashMap::new(),
+        }
+    }
 
-        let (mut write, mut read) = ws_stream.split();
+    /// Maps a `BASE/QUOTE` pair to Bitstamp's `basequote` symbol.
+    fn symbol(instrument: &str) -> String {
+        instrument.to_lowercase().replace('/', "")
+    }
+
+    fn orderbook_channel(instrument: &str) -> String {
+        match SETTINGS.bitstamp.mode {
+            BookMode::Partial => format!("order_book_{}", Self::symbol(instrument)),
+            BookMode::Diff => format!("diff_order_book_{}", Self::symbol(instrument)),
+        }
+    }
+
+    fn request(event: &str, channel: &str) -> Request<PublicChannel> {
+        Request {
+            event: event.to_string(),
+            data: PublicChannel {
+                channel: channel.to_string(),
+            },
+        }
+    }
 
-        Self::subscribe_to_orderbook(&mut write).await;
+    /// Waits for the subscription to be confirmed, skipping anything sent before it.
+    async fn await_subscription(read: &mut ReadStream) -> Result<(), LoopState> {
+        loop {
+            let text = next_text(read).await?;
+            let res = parse_text::<Response>(&text)?;
 
-        let res = read_from_stream::<Response>(&mut read).await.unwrap();
-        assert_eq!("bts:subscription_succeeded", res.event);
+            match res.event.as_str() {
+                "bts:subscription_succeeded" => return Ok(()),
+                "data" | "bts:unsubscription_succeeded" => continue,
+                _ => {
+                    eprintln!("Unexpected subscription response from Bitstamp: {}", text);
+                    return Err(LoopState::Break);
+                }
+            }
+        }
+    }
 
+    /// Waits for the next diff of any subscribed channel.
+    async fn next_diff(
+        &self,
+        read: &mut ReadStream,
+    ) -> Result<(&'static str, Orderbook), LoopState> {
         loop {
-            tokio::select! {
-                res = read_from_stream::<Data<Orderbook>>(&mut read) => {
-                    match res {
-                        Ok(data) => {
-                            self.process_orderbook(data.data).await;
-                        },
-                        Err(state) => {
-                            if let LoopState::Break = state {
-                                break;
-                            }
-                        },
-                    }
-                },
-                _ = self.shutdown_rx.recv() => {
-                    break;
+            let text = next_text(read).await?;
+            if let Ok(data) = serde_json::from_str::<Data<Orderbook>>(&text) {
+                if let Some(&instrument) = self.channels.get(&data.channel) {
+                    return Ok((instrument, data.data));
+                }
+            }
+        }
+    }
+
+    /// Builds the local books from REST snapshots, buffering the diffs received
+    /// while they are fetched and applying the ones newer than the snapshots.
+    async fn sync_books(&mut self, read: &mut ReadStream) -> Result<(), LoopState> {
+        let mut buffer = Vec::new();
+
+        let mut books = HashMap::new();
+        for &instrument in self.channels.values() {
+            let url = format!(
+                "{}/{}/",
+                Self::BITSTAMP_ORDER_BOOK_URL,
+                Self::symbol(instrument)
+            );
+
+            let fetch = fetch_json::<Orderbook>(&url);
+            tokio::pin!(fetch);
+
+            let res = loop {
+                tokio::select! {
+                    res = &mut fetch => break res,
+                    diff = self.next_diff(read) => buffer.push(diff?),
                 }
             };
+
+            let snapshot = res.map_err(|err| {
+                eprintln!("Could not fetch Bitstamp {} snapshot: {}", instrument, err);
+                LoopState::Break
+            })?;
+
+            let mut book = DiffBook::default();
+            book.apply(snapshot);
+            books.insert(instrument, book);
         }
+        self.books = books;
 
-        Self::unsubscribe_from_orderbook(&mut write).await;
+        for (instrument, diff) in buffer {
+            self.apply(instrument, diff);
+        }
 
-        let _ = write.close();
+        Ok(())
+    }
 
-        println!("Exiting bitstamp...");
+    /// Applies a diff to an instrument's local book, returning `false` if it is
+    /// older than the book.
+    fn apply(&mut self, instrument: &'static str, diff: Orderbook) -> bool {
+        match self.books.get_mut(instrument) {
+            Some(book) => book.apply(diff),
+            None => false,
+        }
     }
+}
 
-    async fn process_orderbook(&self, orderbook: Orderbook) {
-        let bids: Vec<_> = orderbook
-            .bids
-            .into_iter()
-            .map(|b| Level {
-                exchange: Self::EXCHANGE,
-                price: b[0].parse().unwrap(),
-                amount: b[1].parse().unwrap(),
-            })
-            .collect();
-
-        let asks: Vec<_> = orderbook
-            .asks
-            .into_iter()
-            .map(|b| Level {
-                exchange: Self::EXCHANGE,
-                price: b[0].parse().unwrap(),
-                amount: b[1].parse().unwrap(),
-            })
-            .collect();
-
-        let levels = Levels {
-            exchange: Self::EXCHANGE,
-            bids,
-            asks,
-        };
+impl DiffBook {
+    fn apply(&mut self, diff: Orderbook) -> bool {
+        let microtimestamp = diff.microtimestamp.parse().unwrap();
+        if microtimestamp <= self.microtimestamp {
+            return false;
+        }
+
+        self.book.update_bids(&diff.bids);
+        self.book.update_asks(&diff.asks);
+        self.microtimestamp = microtimestamp;
+
+        true
+    }
+}
+
+#[async_trait]
+impl Connector for Bitstamp {
+    fn name(&self) -> &'static str {
+        Self::EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(Self::BITSTAMP_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        for channel in self.channels.keys() {
+            let request = Self::request("bts:subscribe", channel);
+
+            if let Err(err) = send_json(write, &request).await {
+                eprintln!("Could not subscribe to Bitstamp: {}", err);
+                return Err(LoopState::Break);
+            }
+
+            Self::await_subscription(read).await?;
+        }
 
-        if let Err(err) = self.levels_tx.send(levels).await {
-            eprintln!("Error sending message: {}", err);
+        if let BookMode::Diff = SETTINGS.bitstamp.mode {
+            self.sync_books(read).await?;
         }
+
+        Ok(())
     }
 
-    async fn subscribe_to_orderbook(write: &mut WriteSink) {
-        let channel = format!("order_book_{}", SETTINGS.bitstamp.currency_pair);
-        let request = Request::<PublicChannel> {
-            event: String::from("bts:subscribe"),
-            data: PublicChannel { channel },
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        let data = match parse_text::<Data<Orderbook>>(text) {
+            Ok(data) => data,
+            Err(_) => return Ok(None),
+        };
+        let instrument = match self.channels.get(&data.channel) {
+            Some(&instrument) => instrument,
+            None => return Ok(None),
         };
+        let orderbook = data.data;
 
-        if let Err(err) = write
-            .send(Message::Text(serde_json::to_string(&request).unwrap()))
-            .await
-        {
-            eprintln!("Could not subscribe to Bitstamp: {}", err);
+        match SETTINGS.bitstamp.mode {
+            BookMode::Partial => Ok(Some(Levels {
+                exchange: Self::EXCHANGE,
+                instrument,
+                bids: parse_levels(Self::EXCHANGE, orderbook.bids),
+                asks: parse_levels(Self::EXCHANGE, orderbook.asks),
+            })),
+            BookMode::Diff => {
+                if self.apply(instrument, orderbook) {
+                    Ok(Some(
+                        self.books[instrument]
+                            .book
+                            .levels(Self::EXCHANGE, instrument),
+                    ))
+                } else {
+                    Ok(None)
+                }
+            }
         }
     }
 
-    async fn unsubscribe_from_orderbook(write: &mut WriteSink) {
-        let channel = format!("order_book_{}", SETTINGS.bitstamp.currency_pair);
-        let request = Request::<PublicChannel> {
-            event: String::from("bts:unsubscribe"),
-            data: PublicChannel { channel },
-        };
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        for channel in self.channels.keys() {
+            let request = Self::request("bts:unsubscribe", channel);
 
-        if let Err(err) = write
-            .send(Message::Text(serde_json::to_string(&request).unwrap()))
-            .await
-        {
-            eprintln!("Could not unsubscribe from Bitstamp: {}", err);
+            if let Err(err) = send_json(write, &request).await {
+                eprintln!("Could not unsubscribe from Bitstamp: {}", err);
+            }
         }
     }
 }
@@ -145,9 +262,11 @@ struct Response {
 
 #[derive(Deserialize)]
 struct Data<T> {
+    channel: String,
     data: T,
 }
 
+/// Both the websocket book messages and the REST order book snapshot.
 #[allow(dead_code)]
 #[derive(Deserialize)]
 struct Orderbook {
@@ -0,0 +1,124 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use rust_decimal::Decimal;
+
+use crate::msg::{Level, Levels};
+
+/// A price level, with the strings the exchange sent it as for venues whose
+/// checksums are computed over them.
+pub struct Entry {
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub raw_price: String,
+    pub raw_amount: String,
+}
+
+impl Entry {
+    fn new(price: &str, amount: &str) -> Entry {
+        Entry {
+            price: price.parse().unwrap(),
+            amount: amount.parse().unwrap(),
+            raw_price: price.to_string(),
+            raw_amount: amount.to_string(),
+        }
+    }
+
+    fn to_level(&self, exchange: &'static str) -> Level {
+        Level {
+            exchange,
+            price: self.price,
+            amount: self.amount,
+            synthetic: false,
+        }
+    }
+}
+
+/// Full-depth book for connectors that apply incremental updates to a snapshot.
+#[derive(Default)]
+pub struct LocalBook {
+    bids: BTreeMap<Reverse<Decimal>, Entry>,
+    asks: BTreeMap<Decimal, Entry>,
+    synced: bool,
+}
+
+impl LocalBook {
+    /// Clears the book for the levels of a snapshot.
+    pub fn snapshot(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.synced = true;
+    }
+
+    /// Whether a snapshot arrived since the book was created. Connectors
+    /// create their books on subscribing and drop updates until then, as
+    /// those don't apply to anything.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Sets the amount at a bid price, removing the level when the amount is zero.
+    pub fn update_bid(&mut self, price: &str, amount: &str) {
+        let entry = Entry::new(price, amount);
+        let key = Reverse(entry.price);
+        if entry.amount.is_zero() {
+            self.bids.remove(&key);
+        } else {
+            self.bids.insert(key, entry);
+        }
+    }
+
+    /// Sets the amount at an ask price, removing the level when the amount is zero.
+    pub fn update_ask(&mut self, price: &str, amount: &str) {
+        let entry = Entry::new(price, amount);
+        let key = entry.price;
+        if entry.amount.is_zero() {
+            self.asks.remove(&key);
+        } else {
+            self.asks.insert(key, entry);
+        }
+    }
+
+    /// Applies `[price, amount]` pairs as sent by most exchanges.
+    pub fn update_bids(&mut self, levels: &[[String; 2]]) {
+        for [price, amount] in levels {
+            self.update_bid(price, amount);
+        }
+    }
+
+    /// Applies `[price, amount]` pairs as sent by most exchanges.
+    pub fn update_asks(&mut self, levels: &[[String; 2]]) {
+        for [price, amount] in levels {
+            self.update_ask(price, amount);
+        }
+    }
+
+    /// Drops the levels beyond `depth` on each side, for venues that only
+    /// maintain the top of the book and never delete levels falling out of it.
+    pub fn truncate(&mut self, depth: usize) {
+        while self.bids.len() > depth {
+            self.bids.pop_last();
+        }
+        while self.asks.len() > depth {
+            self.asks.pop_last();
+        }
+    }
+
+    /// Bids from the best (highest) price down.
+    pub fn bids(&self) -> impl Iterator<Item = &Entry> {
+        self.bids.values()
+    }
+
+    /// Asks from the best (lowest) price up.
+    pub fn asks(&self) -> impl Iterator<Item = &Entry> {
+        self.asks.values()
+    }
+
+    pub fn levels(&self, exchange: &'static str, instrument: &'static str) -> Levels {
+        Levels {
+            exchange,
+            instrument,
+            bids: self.bids().map(|e| e.to_level(exchange)).collect(),
+            asks: self.asks().map(|e| e.to_level(exchange)).collect(),
+        }
+    }
+}
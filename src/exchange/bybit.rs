@@ -0,0 +1,190 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Error;
+
+use crate::{
+    exchange::{
+        book::LocalBook,
+        util::{
+            connect, next_text, parse_text, send_json, symbol_map, LoopState, ReadStream,
+            WriteSink, WsStream,
+        },
+        Connector, Resync,
+    },
+    msg::Levels,
+    SETTINGS,
+};
+
+pub struct Bybit {
+    // Instrument of every subscribed topic
+    topics: HashMap<String, &'static str>,
+    books: HashMap<&'static str, TopicBook>,
+}
+
+#[derive(Default)]
+struct TopicBook {
+    book: LocalBook,
+    update_id: u64,
+}
+
+const EXCHANGE: &str = "Bybit";
+const BYBIT_WSS: &str = "wss://stream.bybit.com/v5/public/spot";
+
+/// Bybit drops connections that haven't sent a ping in a while.
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+
+impl Bybit {
+    pub fn new() -> Bybit {
+        Bybit {
+            topics: symbol_map(SETTINGS.instruments(EXCHANGE), Self::topic),
+            books: HashMap::new(),
+        }
+    }
+
+    /// Maps a `BASE/QUOTE` pair to Bybit's `BASEQUOTE` symbol.
+    fn topic(instrument: &str) -> String {
+        format!(
+            "orderbook.{}.{}",
+            SETTINGS.bybit.depth,
+            instrument.to_uppercase().replace('/', "")
+        )
+    }
+
+    fn request(&self, op: &str) -> Request {
+        Request {
+            op: op.to_string(),
+            args: self.topics.keys().cloned().collect(),
+        }
+    }
+
+    /// Waits for the subscription to be confirmed, skipping anything sent before it.
+    async fn await_subscription(read: &mut ReadStream) -> Result<(), LoopState> {
+        loop {
+            let text = next_text(read).await?;
+            let message = parse_text::<Message>(&text)?;
+
+            if message.op.as_deref() != Some("subscribe") {
+                continue;
+            }
+            if message.success != Some(true) {
+                eprintln!("Bybit subscription failed: {}", text);
+                return Err(LoopState::Break);
+            }
+            return Ok(());
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for Bybit {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(BYBIT_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        self.books = self
+            .topics
+            .values()
+            .map(|&instrument| (instrument, TopicBook::default()))
+            .collect();
+
+        if let Err(err) = send_json(write, &self.request("subscribe")).await {
+            eprintln!("Could not subscribe to Bybit: {}", err);
+            return Err(LoopState::Break);
+        }
+
+        Self::await_subscription(read).await
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        let message = match parse_text::<Message>(text) {
+            Ok(message) => message,
+            Err(_) => return Ok(None),
+        };
+
+        // Pongs and request responses
+        let book = match message.data {
+            Some(book) => book,
+            None => return Ok(None),
+        };
+
+        let instrument = match message.topic.and_then(|topic| self.topics.get(&topic)) {
+            Some(&instrument) => instrument,
+            None => return Ok(None),
+        };
+        let local = match self.books.get_mut(instrument) {
+            Some(local) => local,
+            None => return Ok(None),
+        };
+
+        // An update id of 1 is a snapshot sent after Bybit restarted the service
+        let snapshot = message.kind.as_deref() == Some("snapshot") || book.update_id == 1;
+        if snapshot {
+            local.book.snapshot();
+        } else if !local.book.is_synced() || book.update_id <= local.update_id {
+            return Ok(None);
+        }
+
+        local.book.update_bids(&book.bids);
+        local.book.update_asks(&book.asks);
+        local.book.truncate(SETTINGS.bybit.depth);
+        local.update_id = book.update_id;
+
+        Ok(Some(local.book.levels(EXCHANGE, instrument)))
+    }
+
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        if let Err(err) = send_json(write, &self.request("unsubscribe")).await {
+            eprintln!("Could not unsubscribe from Bybit: {}", err);
+        }
+    }
+
+    fn ping_interval(&self) -> Option<Duration> {
+        Some(PING_INTERVAL)
+    }
+
+    async fn ping(&mut self, write: &mut WriteSink) -> Result<(), Error> {
+        send_json(write, &Ping { op: "ping" }).await
+    }
+}
+
+#[derive(Serialize)]
+struct Request {
+    op: String,
+    args: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Ping {
+    op: &'static str,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    op: Option<String>,
+    success: Option<bool>,
+    topic: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    data: Option<Book>,
+}
+
+#[derive(Deserialize)]
+struct Book {
+    #[serde(rename = "b")]
+    bids: Vec<[String; 2]>,
+    #[serde(rename = "a")]
+    asks: Vec<[String; 2]>,
+    #[serde(rename = "u")]
+    update_id: u64,
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Error;
+
+use crate::{
+    exchange::{
+        book::LocalBook,
+        util::{
+            connect, next_text, parse_text, send_json, symbol_map, LoopState, ReadStream,
+            WriteSink, WsStream,
+        },
+        Connector, Resync,
+    },
+    msg::Levels,
+    SETTINGS,
+};
+
+pub struct Coinbase {
+    // Instrument of every product id
+    products: HashMap<String, &'static str>,
+    books: HashMap<&'static str, ProductBook>,
+}
+
+#[derive(Default)]
+struct ProductBook {
+    book: LocalBook,
+    last_heartbeat: Option<Heartbeat>,
+}
+
+struct Heartbeat {
+    sequence: u64,
+    received: Instant,
+}
+
+const EXCHANGE: &str = "Coinbase";
+const COINBASE_WSS: &str = "wss://ws-feed.exchange.coinbase.com";
+
+impl Coinbase {
+    pub fn new() -> Coinbase {
+        Coinbase {
+            products: symbol_map(SETTINGS.instruments(EXCHANGE), Self::product_id),
+            books: HashMap::new(),
+        }
+    }
+
+    /// Maps a `BASE/QUOTE` pair to Coinbase's `BASE-QUOTE` product id.
+    fn product_id(instrument: &str) -> String {
+        instrument.to_uppercase().replace('/', "-")
+    }
+
+    fn request(&self, kind: &str) -> Request {
+        Request {
+            kind: kind.to_string(),
+            product_ids: self.products.keys().cloned().collect(),
+            channels: vec![String::from("level2_batch"), String::from("heartbeat")],
+        }
+    }
+
+    /// Waits for the subscriptions to be confirmed, skipping anything sent before it.
+    async fn await_subscriptions(read: &mut ReadStream) -> Result<(), LoopState> {
+        loop {
+            let text = next_text(read).await?;
+            match parse_text::<Message>(&text)? {
+                Message::Subscriptions => return Ok(()),
+                Message::Error { message } => {
+                    eprintln!("Coinbase subscription failed: {}", message);
+                    return Err(LoopState::Break);
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl ProductBook {
+    /// Heartbeats carry the product's latest sequence number and arrive every
+    /// second; a sequence going backwards or a heartbeat going missing means
+    /// the feed dropped messages and the book can't be trusted anymore.
+    fn check_heartbeat(&mut self, sequence: u64) -> Result<(), Resync> {
+        let now = Instant::now();
+        let max_gap = Duration::from_millis(SETTINGS.coinbase.max_heartbeat_gap_ms);
+
+        if let Some(last) = &self.last_heartbeat {
+            if sequence < last.sequence {
+                eprintln!(
+                    "Coinbase sequence went backwards: {} after {}",
+                    sequence, last.sequence
+                );
+                return Err(Resync);
+            }
+            if now.duration_since(last.received) > max_gap {
+                eprintln!(
+                    "Coinbase heartbeats missed, last sequence {}",
+                    last.sequence
+                );
+                return Err(Resync);
+            }
+        }
+
+        self.last_heartbeat = Some(Heartbeat {
+            sequence,
+            received: now,
+        });
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Connector for Coinbase {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(COINBASE_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        self.books = self
+            .products
+            .values()
+            .map(|&instrument| (instrument, ProductBook::default()))
+            .collect();
+
+        if let Err(err) = send_json(write, &self.request("subscribe")).await {
+            eprintln!("Could not subscribe to Coinbase: {}", err);
+            return Err(LoopState::Break);
+        }
+
+        Self::await_subscriptions(read).await
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        let message = match parse_text::<Message>(text) {
+            Ok(message) => message,
+            Err(_) => return Ok(None),
+        };
+
+        let product_id = match &message {
+            Message::Snapshot { product_id, .. }
+            | Message::L2Update { product_id, .. }
+            | Message::Heartbeat { product_id, .. } => product_id,
+            Message::Error { message } => {
+                eprintln!("Coinbase error: {}", message);
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        };
+        let instrument = match self.products.get(product_id) {
+            Some(&instrument) => instrument,
+            None => return Ok(None),
+        };
+        let product = match self.books.get_mut(instrument) {
+            Some(product) => product,
+            None => return Ok(None),
+        };
+
+        match message {
+            Message::Snapshot { bids, asks, .. } => {
+                product.book.snapshot();
+                product.book.update_bids(&bids);
+                product.book.update_asks(&asks);
+            }
+            Message::L2Update { changes, .. } if product.book.is_synced() => {
+                for [side, price, size] in changes {
+                    match side.as_str() {
+                        "buy" => product.book.update_bid(&price, &size),
+                        "sell" => product.book.update_ask(&price, &size),
+                        _ => eprintln!("Unknown Coinbase side: {}", side),
+                    }
+                }
+            }
+            Message::Heartbeat { sequence, .. } => {
+                product.check_heartbeat(sequence)?;
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        }
+
+        Ok(Some(product.book.levels(EXCHANGE, instrument)))
+    }
+
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        if let Err(err) = send_json(write, &self.request("unsubscribe")).await {
+            eprintln!("Could not unsubscribe from Coinbase: {}", err);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Request {
+    #[serde(rename = "type")]
+    kind: String,
+    product_ids: Vec<String>,
+    channels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message {
+    Subscriptions,
+    Snapshot {
+        product_id: String,
+        bids: Vec<[String; 2]>,
+        asks: Vec<[String; 2]>,
+    },
+    L2Update {
+        product_id: String,
+        changes: Vec<[String; 3]>,
+    },
+    Heartbeat {
+        product_id: String,
+        sequence: u64,
+    },
+    Error {
+        message: String,
+    },
+    #[serde(other)]
+    Other,
+}
@@ -0,0 +1,347 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant, Interval},
+};
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+use crate::{
+    exchange::{
+        backoff::Backoff,
+        util::{read_frame, LoopState, ReadStream, WriteSink, WsStream},
+    },
+    msg::{Levels, Update},
+    shutdown, SETTINGS,
+};
+
+/// How often a connection receiving frames tells the orderbook it's alive.
+const ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A venue publishing an order book over a websocket.
+///
+/// `run` drives every connector through the same
+/// connect/subscribe/read/unsubscribe cycle, so a connector only has to
+/// describe its own protocol.
+#[async_trait]
+pub trait Connector: Send {
+    /// Name attached to every level coming from this venue.
+    fn name(&self) -> &'static str;
+
+    /// Instruments the venue streams books for.
+    fn instruments(&self) -> &'static [String] {
+        SETTINGS.instruments(self.name())
+    }
+
+    /// Opens the websocket connection.
+    async fn connect(&mut self) -> Result<WsStream, Error>;
+
+    /// Subscribes to the order book and waits for the venue to confirm it.
+    ///
+    /// Venues whose confirmations may arrive after the first book messages
+    /// leave them to `parse` instead, as messages read here never reach it.
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState>;
+
+    /// Turns a binary frame into text, for venues that compress their messages.
+    fn decode(&self, _data: &[u8]) -> Option<String> {
+        None
+    }
+
+    /// Returns the answer to a message the venue expects a reply to, such as
+    /// a server-initiated ping. Messages answered here aren't passed to `parse`.
+    fn reply(&mut self, _text: &str) -> Option<String> {
+        None
+    }
+
+    /// Parses a text message, returning `None` for anything that isn't book data.
+    ///
+    /// Connectors keeping a local book return `Resync` when an update can't be
+    /// applied to it, which makes `run` unsubscribe and subscribe again.
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync>;
+
+    /// Unsubscribes from the order book.
+    async fn unsubscribe(&mut self, write: &mut WriteSink);
+
+    /// How often `ping` has to be called to keep the connection alive, for
+    /// venues expecting application-level keepalives.
+    fn ping_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Sends an application-level keepalive.
+    async fn ping(&mut self, _write: &mut WriteSink) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The local book missed an update and has to be rebuilt.
+#[derive(Debug)]
+pub struct Resync;
+
+enum Exit {
+    Shutdown,
+    Disconnected,
+}
+
+/// Streams the connector's book until shutdown, reconnecting with backoff
+/// whenever the connection drops.
+pub async fn run(
+    mut connector: Box<dyn Connector>,
+    levels_tx: mpsc::Sender<Update>,
+    mut shutdown_rx: shutdown::Receiver,
+) {
+    let name = connector.name();
+    let mut backoff = Backoff::new();
+
+    loop {
+        if let Exit::Shutdown = stream(
+            connector.as_mut(),
+            &levels_tx,
+            &mut shutdown_rx,
+            &mut backoff,
+        )
+        .await
+        {
+            break;
+        }
+
+        // Don't let the last books from a dead connection linger in the summary
+        clear(name, connector.instruments(), &levels_tx).await;
+
+        let delay = backoff.next_delay();
+        eprintln!("{} disconnected, reconnecting in {:?}", name, delay);
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+
+    println!("Exiting {}...", name.to_lowercase());
+}
+
+async fn stream(
+    connector: &mut dyn Connector,
+    levels_tx: &mpsc::Sender<Update>,
+    shutdown_rx: &mut shutdown::Receiver,
+    backoff: &mut Backoff,
+) -> Exit {
+    let name = connector.name();
+
+    let ws_stream = tokio::select! {
+        res = connector.connect() => {
+            match res {
+                Ok(ws_stream) => ws_stream,
+                Err(err) => {
+                    eprintln!("Failed to connect to {}: {}", name, err);
+                    return Exit::Disconnected;
+                }
+            }
+        },
+        _ = time::sleep(idle_timeout(name)) => {
+            eprintln!("Timed out connecting to {}", name);
+            return Exit::Disconnected;
+        },
+        _ = shutdown_rx.recv() => return Exit::Shutdown,
+    };
+
+    let (mut write, mut read) = ws_stream.split();
+
+    if let Err(exit) = subscribe(connector, &mut write, &mut read, shutdown_rx).await {
+        return exit;
+    }
+
+    backoff.reset();
+
+    let mut ping = connector.ping_interval().map(interval_after);
+    let mut ws_ping = ws_ping_interval().map(interval_after);
+
+    // Half-open connections don't error, they just go quiet
+    let idle_timeout = idle_timeout(name);
+    let idle = time::sleep(idle_timeout);
+    tokio::pin!(idle);
+
+    // Books that don't change aren't resent, so the orderbook is told the
+    // venue is still there to keep them from being taken as stale
+    let mut alive_sent = Instant::now();
+
+    let exit = loop {
+        tokio::select! {
+            res = read_frame(&mut read) => {
+                let text = match res {
+                    Ok(Message::Text(text)) => Some(text),
+                    Ok(Message::Binary(data)) => connector.decode(&data),
+                    Ok(Message::Ping(_)) => {
+                        // tungstenite queued the pong when reading the ping,
+                        // flushing sends it now instead of on the next read
+                        if let Err(err) = write.flush().await {
+                            eprintln!("Could not answer ping from {}: {}", name, err);
+                            break Exit::Disconnected;
+                        }
+                        None
+                    }
+                    Ok(_) => None,
+                    Err(LoopState::Continue) => None,
+                    Err(LoopState::Break) => break Exit::Disconnected,
+                };
+
+                if let Some(text) = text {
+                    if let Err(exit) =
+                        handle(connector, &text, &mut write, &mut read, levels_tx, shutdown_rx).await
+                    {
+                        break exit;
+                    }
+                }
+
+                idle.as_mut().reset(Instant::now() + idle_timeout);
+
+                if alive_sent.elapsed() >= ALIVE_INTERVAL {
+                    alive_sent = Instant::now();
+                    let alive = Update::Alive {
+                        exchange: name,
+                        instruments: connector.instruments(),
+                    };
+                    if let Err(err) = levels_tx.send(alive).await {
+                        eprintln!("Error sending message: {}", err);
+                    }
+                }
+            },
+            _ = &mut idle => {
+                eprintln!("Nothing received from {} for {:?}", name, idle_timeout);
+                break Exit::Disconnected;
+            },
+            _ = async { ping.as_mut().unwrap().tick().await }, if ping.is_some() => {
+                if let Err(err) = connector.ping(&mut write).await {
+                    eprintln!("Could not ping {}: {}", name, err);
+                    break Exit::Disconnected;
+                }
+            },
+            _ = async { ws_ping.as_mut().unwrap().tick().await }, if ws_ping.is_some() => {
+                if let Err(err) = write.send(Message::Ping(Vec::new())).await {
+                    eprintln!("Could not ping {}: {}", name, err);
+                    break Exit::Disconnected;
+                }
+            },
+            _ = shutdown_rx.recv() => {
+                break Exit::Shutdown;
+            }
+        };
+    };
+
+    if let Exit::Shutdown = exit {
+        connector.unsubscribe(&mut write).await;
+    }
+
+    let _ = write.close().await;
+
+    exit
+}
+
+async fn handle(
+    connector: &mut dyn Connector,
+    text: &str,
+    write: &mut WriteSink,
+    read: &mut ReadStream,
+    levels_tx: &mpsc::Sender<Update>,
+    shutdown_rx: &mut shutdown::Receiver,
+) -> Result<(), Exit> {
+    let name = connector.name();
+
+    if let Some(reply) = connector.reply(text) {
+        if let Err(err) = write.send(Message::Text(reply)).await {
+            eprintln!("Could not reply to {}: {}", name, err);
+            return Err(Exit::Disconnected);
+        }
+        return Ok(());
+    }
+
+    match connector.parse(text) {
+        Ok(Some(levels)) => {
+            if let Err(err) = levels_tx.send(Update::Levels(levels)).await {
+                eprintln!("Error sending message: {}", err);
+            }
+        }
+        Ok(None) => {}
+        Err(Resync) => {
+            eprintln!("{} book out of sync, resubscribing", name);
+
+            clear(name, connector.instruments(), levels_tx).await;
+
+            connector.unsubscribe(write).await;
+            subscribe(connector, write, read, shutdown_rx).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the venue's levels for every instrument from the summaries.
+async fn clear(
+    name: &'static str,
+    instruments: &'static [String],
+    levels_tx: &mpsc::Sender<Update>,
+) {
+    for instrument in instruments {
+        if let Err(err) = levels_tx
+            .send(Update::Levels(Levels::empty(name, instrument)))
+            .await
+        {
+            eprintln!("Error sending message: {}", err);
+        }
+    }
+}
+
+async fn subscribe(
+    connector: &mut dyn Connector,
+    write: &mut WriteSink,
+    read: &mut ReadStream,
+    shutdown_rx: &mut shutdown::Receiver,
+) -> Result<(), Exit> {
+    let name = connector.name();
+
+    tokio::select! {
+        res = connector.subscribe(write, read) => {
+            if res.is_err() {
+                eprintln!("Could not subscribe to {}", name);
+                return Err(Exit::Disconnected);
+            }
+        },
+        _ = time::sleep(idle_timeout(name)) => {
+            eprintln!("Timed out subscribing to {}", name);
+            return Err(Exit::Disconnected);
+        },
+        _ = shutdown_rx.recv() => return Err(Exit::Shutdown),
+    };
+
+    Ok(())
+}
+
+/// How long a venue may stay silent before its connection is considered dead.
+fn idle_timeout(name: &str) -> Duration {
+    let heartbeat = &SETTINGS.heartbeat;
+    let ms = heartbeat
+        .venue_idle_timeout_ms
+        .get(&name.to_lowercase())
+        .copied()
+        .unwrap_or(heartbeat.idle_timeout_ms);
+    Duration::from_millis(ms)
+}
+
+/// Interval between websocket pings, `None` when they are disabled.
+fn ws_ping_interval() -> Option<Duration> {
+    match SETTINGS.heartbeat.ping_interval_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// An interval whose first tick is one period away rather than immediate.
+fn interval_after(period: Duration) -> Interval {
+    time::interval_at(Instant::now() + period, period)
+}
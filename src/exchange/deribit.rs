@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::Error;
+
+use crate::{
+    exchange::{
+        book::LocalBook,
+        jsonrpc::{self, Incoming},
+        util::{connect, parse_text, symbol_map, LoopState, ReadStream, WriteSink, WsStream},
+        Connector, Resync,
+    },
+    msg::Levels,
+    SETTINGS,
+};
+
+pub struct Deribit {
+    rpc: jsonrpc::Client,
+    // Instrument of every subscribed channel
+    channels: HashMap<String, &'static str>,
+    books: HashMap<&'static str, InstrumentBook>,
+}
+
+#[derive(Default)]
+struct InstrumentBook {
+    book: LocalBook,
+    change_id: u64,
+}
+
+const EXCHANGE: &str = "Deribit";
+const DERIBIT_WSS: &str = "wss://www.deribit.com/ws/api/v2";
+
+impl Deribit {
+    pub fn new() -> Deribit {
+        Deribit {
+            rpc: jsonrpc::Client::default(),
+            channels: symbol_map(&SETTINGS.deribit.instruments, Self::channel),
+            books: HashMap::new(),
+        }
+    }
+
+    fn channel(instrument: &str) -> String {
+        format!("book.{}.{}", instrument, SETTINGS.deribit.interval)
+    }
+
+    fn channel_list(&self) -> Vec<String> {
+        let mut channels: Vec<_> = self.channels.keys().cloned().collect();
+        channels.sort();
+        channels
+    }
+}
+
+impl InstrumentBook {
+    fn apply(&mut self, book: Book) -> Result<bool, Resync> {
+        match book.kind.as_str() {
+            "snapshot" => self.book.snapshot(),
+            "change" if !self.book.is_synced() => return Ok(false),
+            "change" if book.prev_change_id != Some(self.change_id) => {
+                eprintln!(
+                    "Deribit {} change gap: expected previous change {}, got {:?}",
+                    book.instrument_name, self.change_id, book.prev_change_id
+                );
+                return Err(Resync);
+            }
+            "change" => {}
+            _ => return Ok(false),
+        }
+
+        for (_, price, amount) in book.bids {
+            self.book
+                .update_bid(&price.to_string(), &amount.to_string());
+        }
+        for (_, price, amount) in book.asks {
+            self.book
+                .update_ask(&price.to_string(), &amount.to_string());
+        }
+        self.change_id = book.change_id;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Connector for Deribit {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    fn instruments(&self) -> &'static [String] {
+        &SETTINGS.deribit.instruments
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(DERIBIT_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        self.books = self
+            .channels
+            .values()
+            .map(|&instrument| (instrument, InstrumentBook::default()))
+            .collect();
+        self.rpc.reset();
+
+        // Deribit then sends test requests we have to answer, see `reply`
+        let params = json!({ "interval": SETTINGS.deribit.heartbeat_interval });
+        self.rpc
+            .call(write, read, "public/set_heartbeat", params)
+            .await?;
+
+        let params = json!({ "channels": self.channel_list() });
+        let result = self
+            .rpc
+            .call(write, read, "public/subscribe", params)
+            .await?;
+
+        let mut subscribed: Vec<String> =
+            serde_json::from_value(result.clone()).unwrap_or_default();
+        subscribed.sort();
+        if subscribed != self.channel_list() {
+            eprintln!("Unexpected subscription result from Deribit: {}", result);
+            return Err(LoopState::Break);
+        }
+
+        Ok(())
+    }
+
+    fn reply(&mut self, text: &str) -> Option<String> {
+        let incoming = serde_json::from_str::<Incoming>(text).ok()?;
+        if incoming.method.as_deref() != Some("heartbeat")
+            || incoming.params["type"] != "test_request"
+        {
+            return None;
+        }
+
+        let (_, request) = self.rpc.request("public/test", json!({}));
+        Some(request)
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        let incoming = match parse_text::<Incoming>(text) {
+            Ok(incoming) => incoming,
+            Err(_) => return Ok(None),
+        };
+
+        if incoming.id.is_some() {
+            self.rpc.complete(incoming);
+            return Ok(None);
+        }
+
+        if incoming.method.as_deref() != Some("subscription") {
+            return Ok(None);
+        }
+
+        let notification = match serde_json::from_value::<Subscription>(incoming.params) {
+            Ok(notification) => notification,
+            Err(err) => {
+                eprintln!("Error parsing message: {}\n{}", err, text);
+                return Ok(None);
+            }
+        };
+
+        let instrument = match self.channels.get(&notification.channel) {
+            Some(&instrument) => instrument,
+            None => return Ok(None),
+        };
+        let local = match self.books.get_mut(instrument) {
+            Some(local) => local,
+            None => return Ok(None),
+        };
+
+        if local.apply(notification.data)? {
+            Ok(Some(local.book.levels(EXCHANGE, instrument)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        let params = json!({ "channels": self.channel_list() });
+        if let Err(err) = self.rpc.send(write, "public/unsubscribe", params).await {
+            eprintln!("Could not unsubscribe from Deribit: {}", err);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Subscription {
+    channel: String,
+    data: Book,
+}
+
+/// Levels are `[action, price, amount]` where action is `new`, `change` or
+/// `delete`; deleted levels have a zero amount.
+#[derive(Deserialize)]
+struct Book {
+    #[serde(rename = "type")]
+    kind: String,
+    instrument_name: String,
+    change_id: u64,
+    prev_change_id: Option<u64>,
+    bids: Vec<(String, Decimal, Decimal)>,
+    asks: Vec<(String, Decimal, Decimal)>,
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures_util::SinkExt;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+use crate::{
+    exchange::{
+        util::{
+            connect, gunzip, read_frame, send_json, symbol_map, LoopState, ReadStream, WriteSink,
+            WsStream,
+        },
+        Connector, Resync,
+    },
+    msg::{Level, Levels},
+    SETTINGS,
+};
+
+pub struct Htx {
+    request_id: usize,
+    // Instrument of every subscribed topic
+    topics: HashMap<String, &'static str>,
+}
+
+const EXCHANGE: &str = "HTX";
+const HTX_WSS: &str = "wss://api.huobi.pro/ws";
+
+impl Htx {
+    pub fn new() -> Htx {
+        Htx {
+            request_id: 0,
+            topics: symbol_map(SETTINGS.instruments(EXCHANGE), Self::topic),
+        }
+    }
+
+    fn next_request_id(&mut self) -> String {
+        self.request_id += 1;
+        self.request_id.to_string()
+    }
+
+    /// Maps a `BASE/QUOTE` pair to HTX's `basequote` symbol.
+    fn topic(instrument: &str) -> String {
+        format!(
+            "market.{}.mbp.refresh.{}",
+            instrument.to_lowercase().replace('/', ""),
+            SETTINGS.htx.depth
+        )
+    }
+
+    /// Waits for the response to request `id`, answering pings sent before it.
+    async fn await_response(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+        id: &str,
+    ) -> Result<(), LoopState> {
+        loop {
+            let text = match read_frame(read).await {
+                Ok(Message::Binary(data)) => gunzip(&data),
+                Ok(_) | Err(LoopState::Continue) => None,
+                Err(state) => return Err(state),
+            };
+            let text = match text {
+                Some(text) => text,
+                None => continue,
+            };
+
+            if let Some(reply) = self.reply(&text) {
+                if let Err(err) = write.send(Message::Text(reply)).await {
+                    eprintln!("Could not reply to HTX: {}", err);
+                    return Err(LoopState::Break);
+                }
+                continue;
+            }
+
+            if let Ok(res) = serde_json::from_str::<Response>(&text) {
+                if res.id.as_deref() != Some(id) {
+                    continue;
+                }
+                if res.status != "ok" {
+                    eprintln!("Unexpected response from HTX: {}", text);
+                    return Err(LoopState::Break);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for Htx {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(HTX_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        let topics: Vec<_> = self.topics.keys().cloned().collect();
+        for topic in topics {
+            let id = self.next_request_id();
+            let request = Sub {
+                sub: topic,
+                id: id.clone(),
+            };
+
+            if let Err(err) = send_json(write, &request).await {
+                eprintln!("Could not subscribe to HTX: {}", err);
+                return Err(LoopState::Break);
+            }
+
+            self.await_response(write, read, &id).await?;
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<String> {
+        gunzip(data)
+    }
+
+    fn reply(&mut self, text: &str) -> Option<String> {
+        let ping = serde_json::from_str::<Ping>(text).ok()?;
+        Some(serde_json::to_string(&Pong { pong: ping.ping }).unwrap())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        // Request responses carry no tick
+        let (topic, tick) = match serde_json::from_str::<Data>(text) {
+            Ok(Data {
+                ch: Some(topic),
+                tick: Some(tick),
+            }) => (topic, tick),
+            Ok(_) => return Ok(None),
+            Err(err) => {
+                eprintln!("Error parsing message: {}\n{}", err, text);
+                return Ok(None);
+            }
+        };
+        let instrument = match self.topics.get(&topic) {
+            Some(&instrument) => instrument,
+            None => return Ok(None),
+        };
+
+        let to_level = |[price, amount]: [Decimal; 2]| Level {
+            exchange: EXCHANGE,
+            price,
+            amount,
+            synthetic: false,
+        };
+
+        Ok(Some(Levels {
+            exchange: EXCHANGE,
+            instrument,
+            bids: tick.bids.into_iter().map(to_level).collect(),
+            asks: tick.asks.into_iter().map(to_level).collect(),
+        }))
+    }
+
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        let topics: Vec<_> = self.topics.keys().cloned().collect();
+        for topic in topics {
+            let request = Unsub {
+                unsub: topic,
+                id: self.next_request_id(),
+            };
+
+            if let Err(err) = send_json(write, &request).await {
+                eprintln!("Could not unsubscribe from HTX: {}", err);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Sub {
+    sub: String,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct Unsub {
+    unsub: String,
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: Option<String>,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct Ping {
+    ping: u64,
+}
+
+#[derive(Serialize)]
+struct Pong {
+    pong: u64,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    ch: Option<String>,
+    tick: Option<Tick>,
+}
+
+#[derive(Deserialize)]
+struct Tick {
+    bids: Vec<[Decimal; 2]>,
+    asks: Vec<[Decimal; 2]>,
+}
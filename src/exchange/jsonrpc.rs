@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite;
+
+use crate::exchange::util::{next_text, send_text, LoopState, ReadStream, WriteSink};
+
+/// JSON-RPC 2.0 over a websocket, pairing responses with the requests that caused them.
+#[derive(Default)]
+pub struct Client {
+    next_id: u64,
+    // Method of every request still waiting for its response
+    pending: HashMap<u64, String>,
+}
+
+impl Client {
+    /// Registers a request and returns it serialized, ready to be sent.
+    pub fn request(&mut self, method: &str, params: Value) -> (u64, String) {
+        let (id, request) = self.serialize(method, params);
+        self.pending.insert(id, method.to_string());
+        (id, request)
+    }
+
+    fn serialize(&mut self, method: &str, params: Value) -> (u64, String) {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let request = Request {
+            jsonrpc: "2.0",
+            id,
+            method,
+            params,
+        };
+
+        (id, serde_json::to_string(&request).unwrap())
+    }
+
+    /// Sends a request without registering it, for requests sent right before
+    /// the connection closes whose response never gets read.
+    pub async fn send(
+        &mut self,
+        write: &mut WriteSink,
+        method: &str,
+        params: Value,
+    ) -> Result<(), tungstenite::Error> {
+        let (_, request) = self.serialize(method, params);
+        send_text(write, request).await
+    }
+
+    /// Forgets the requests still waiting for their response, which won't
+    /// arrive once the connection they were sent on is gone.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Sends a request and waits for its result, skipping any other message.
+    pub async fn call(
+        &mut self,
+        write: &mut WriteSink,
+        read: &mut ReadStream,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, LoopState> {
+        let (id, request) = self.request(method, params);
+
+        if let Err(err) = send_text(write, request).await {
+            eprintln!("Could not send {}: {}", method, err);
+            return Err(LoopState::Break);
+        }
+
+        loop {
+            let text = next_text(read).await?;
+            let incoming = match serde_json::from_str::<Incoming>(&text) {
+                Ok(incoming) => incoming,
+                Err(_) => continue,
+            };
+
+            if incoming.id != Some(id) {
+                self.complete(incoming);
+                continue;
+            }
+
+            return self.complete(incoming).ok_or(LoopState::Break);
+        }
+    }
+
+    /// Matches a response with its request, returning the result or logging
+    /// the error. Notifications and unknown responses return `None`.
+    pub fn complete(&mut self, incoming: Incoming) -> Option<Value> {
+        let method = self.pending.remove(&incoming.id?)?;
+
+        if let Some(error) = incoming.error {
+            eprintln!("{} failed: {} ({})", method, error.message, error.code);
+            return None;
+        }
+
+        incoming.result
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'a str,
+    params: Value,
+}
+
+/// Either a response, which has an id, or a notification, which has a method.
+#[derive(Deserialize)]
+pub struct Incoming {
+    pub id: Option<u64>,
+    pub method: Option<String>,
+    #[serde(default)]
+    pub params: Value,
+    result: Option<Value>,
+    error: Option<Error>,
+}
+
+#[derive(Deserialize)]
+struct Error {
+    code: i64,
+    message: String,
+}
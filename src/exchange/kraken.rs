@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Error;
+
+use crate::{
+    exchange::{
+        book::LocalBook,
+        util::{connect, parse_text, send_json, LoopState, ReadStream, WriteSink, WsStream},
+        Connector, Resync,
+    },
+    msg::Levels,
+    settings::Precision,
+    SETTINGS,
+};
+
+pub struct Kraken {
+    request_id: usize,
+    books: HashMap<&'static str, SymbolBook>,
+}
+
+#[derive(Default)]
+struct SymbolBook {
+    book: LocalBook,
+}
+
+const EXCHANGE: &str = "Kraken";
+const KRAKEN_WSS: &str = "wss://ws.kraken.com/v2";
+
+/// Levels per side covered by the book checksum.
+const CHECKSUM_DEPTH: usize = 10;
+
+impl Kraken {
+    pub fn new() -> Kraken {
+        // Fail on startup rather than on the first book of an instrument
+        for instrument in SETTINGS.instruments(EXCHANGE) {
+            Self::precision(instrument);
+        }
+
+        Kraken {
+            request_id: 0,
+            books: HashMap::new(),
+        }
+    }
+
+    fn next_request_id(&mut self) -> usize {
+        self.request_id += 1;
+        self.request_id
+    }
+
+    fn book_params() -> Params {
+        Params {
+            channel: String::from("book"),
+            // Kraken's v2 symbols are written `BASE/QUOTE` as well
+            symbol: SETTINGS.instruments(EXCHANGE).to_vec(),
+            depth: SETTINGS.kraken.depth,
+        }
+    }
+
+    fn precision(instrument: &str) -> Precision {
+        *SETTINGS
+            .kraken
+            .precision
+            .get(instrument)
+            .unwrap_or_else(|| panic!("No Kraken precision configured for {}", instrument))
+    }
+}
+
+impl SymbolBook {
+    /// Applies the levels of a message, dropping those falling beyond `depth`.
+    fn apply(&mut self, bids: Vec<BookLevel>, asks: Vec<BookLevel>, depth: usize) {
+        for level in bids {
+            self.book
+                .update_bid(&level.price.to_string(), &level.qty.to_string());
+        }
+        for level in asks {
+            self.book
+                .update_ask(&level.price.to_string(), &level.qty.to_string());
+        }
+        self.book.truncate(depth);
+    }
+
+    /// CRC32 of the top asks then the top bids, each level written as its price
+    /// followed by its quantity, formatted to the instrument's precision with
+    /// the decimal point and leading zeros removed.
+    fn checksum(&self, precision: Precision) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+
+        let asks = self.book.asks().take(CHECKSUM_DEPTH);
+        let bids = self.book.bids().take(CHECKSUM_DEPTH);
+        for entry in asks.chain(bids) {
+            hasher.update(checksum_field(entry.price, precision.price).as_bytes());
+            hasher.update(checksum_field(entry.amount, precision.qty).as_bytes());
+        }
+
+        hasher.finalize()
+    }
+}
+
+fn checksum_field(value: Decimal, precision: usize) -> String {
+    format!("{:.*}", precision, value)
+        .replace('.', "")
+        .trim_start_matches('0')
+        .to_string()
+}
+
+#[async_trait]
+impl Connector for Kraken {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(KRAKEN_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        _read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        self.books = SETTINGS
+            .instruments(EXCHANGE)
+            .iter()
+            .map(|instrument| (instrument.as_str(), SymbolBook::default()))
+            .collect();
+
+        let request = Request {
+            method: String::from("subscribe"),
+            params: Self::book_params(),
+            req_id: self.next_request_id(),
+        };
+
+        // Acks come one per symbol and may follow the first snapshots, so
+        // they are checked by `parse` rather than waited for
+        if let Err(err) = send_json(write, &request).await {
+            eprintln!("Could not subscribe to Kraken: {}", err);
+            return Err(LoopState::Break);
+        }
+
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        let message = match parse_text::<Message>(text) {
+            Ok(message) => message,
+            Err(_) => return Ok(None),
+        };
+
+        if message.success == Some(false) {
+            eprintln!("Kraken request failed: {}", text);
+            return Ok(None);
+        }
+
+        // Heartbeats, status updates and request responses
+        if message.channel.as_deref() != Some("book") {
+            return Ok(None);
+        }
+
+        let books = match serde_json::from_value::<Vec<Book>>(message.data) {
+            Ok(books) => books,
+            Err(err) => {
+                eprintln!("Error parsing message: {}\n{}", err, text);
+                return Ok(None);
+            }
+        };
+
+        // Every message carries a single symbol's book
+        let book = match books.into_iter().next() {
+            Some(book) => book,
+            None => return Ok(None),
+        };
+        let instrument = match self.books.get_key_value(book.symbol.as_str()) {
+            Some((&instrument, _)) => instrument,
+            None => return Ok(None),
+        };
+        let local = self.books.get_mut(instrument).unwrap();
+
+        match message.kind.as_deref() {
+            Some("snapshot") => local.book.snapshot(),
+            Some("update") if local.book.is_synced() => {}
+            _ => return Ok(None),
+        }
+
+        local.apply(book.bids, book.asks, SETTINGS.kraken.depth);
+
+        let checksum = local.checksum(Self::precision(instrument));
+        if checksum != book.checksum {
+            eprintln!(
+                "Kraken {} checksum mismatch: expected {}, computed {}",
+                instrument, book.checksum, checksum
+            );
+            return Err(Resync);
+        }
+
+        Ok(Some(local.book.levels(EXCHANGE, instrument)))
+    }
+
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        let request = Request {
+            method: String::from("unsubscribe"),
+            params: Self::book_params(),
+            req_id: self.next_request_id(),
+        };
+
+        if let Err(err) = send_json(write, &request).await {
+            eprintln!("Could not unsubscribe from Kraken: {}", err);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Request {
+    method: String,
+    params: Params,
+    req_id: usize,
+}
+
+#[derive(Serialize)]
+struct Params {
+    channel: String,
+    symbol: Vec<String>,
+    depth: usize,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    channel: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    data: Value,
+    /// Set on request responses.
+    success: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Book {
+    symbol: String,
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    checksum: u32,
+}
+
+#[derive(Deserialize)]
+struct BookLevel {
+    price: Decimal,
+    qty: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Levels as Kraken sends them, prices and quantities being JSON floats.
+    fn book_levels(json: &str) -> Vec<BookLevel> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn checksum_field_formats_to_precision() {
+        // Floats decode without their trailing zeros
+        assert_eq!(checksum_field(Decimal::new(45287, 0), 1), "452870");
+        assert_eq!(checksum_field(Decimal::new(1, 3), 8), "100000");
+        assert_eq!(checksum_field(Decimal::new(2, 0), 8), "200000000");
+    }
+
+    #[test]
+    fn checksum_of_documented_book() {
+        // The BTC/USD book of Kraken's v2 checksum guide, with an eleventh level
+        // a side the checksum has to leave out
+        let asks = book_levels(
+            r#"[
+                {"price": 45285.2, "qty": 0.00100000},
+                {"price": 45286.4, "qty": 1.54582015},
+                {"price": 45286.6, "qty": 1.54579639},
+                {"price": 45286.8, "qty": 1.54577263},
+                {"price": 45287.0, "qty": 1.54574887},
+                {"price": 45287.2, "qty": 1.54572511},
+                {"price": 45287.4, "qty": 1.54570135},
+                {"price": 45287.6, "qty": 1.54567759},
+                {"price": 45287.8, "qty": 1.54565383},
+                {"price": 45288.0, "qty": 1.54563007},
+                {"price": 45288.2, "qty": 1.54560631}
+            ]"#,
+        );
+        let bids = book_levels(
+            r#"[
+                {"price": 45283.5, "qty": 0.10000000},
+                {"price": 45283.4, "qty": 1.54582015},
+                {"price": 45282.1, "qty": 1.54584359},
+                {"price": 45281.0, "qty": 0.10000000},
+                {"price": 45280.3, "qty": 1.54582671},
+                {"price": 45279.0, "qty": 2.00000000},
+                {"price": 45277.6, "qty": 0.03100000},
+                {"price": 45277.1, "qty": 1.54583047},
+                {"price": 45276.6, "qty": 1.54579891},
+                {"price": 45276.2, "qty": 1.54581427},
+                {"price": 45275.9, "qty": 1.54580012}
+            ]"#,
+        );
+
+        let mut book = SymbolBook::default();
+        book.apply(bids, asks, 25);
+
+        let precision = Precision { price: 1, qty: 8 };
+        assert_eq!(book.checksum(precision), 1502079611);
+    }
+}
@@ -1,7 +1,19 @@
+mod backoff;
 mod binance;
-pub use binance::Binance;
-
 mod bitstamp;
-pub use bitstamp::Bitstamp;
+mod book;
+mod bybit;
+mod coinbase;
+mod deribit;
+mod htx;
+mod jsonrpc;
+mod kraken;
+mod okx;
+
+mod connector;
+pub use connector::{run, Connector, Resync};
+
+mod registry;
+pub use registry::create;
 
 mod util;
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Error;
+
+use crate::{
+    exchange::{
+        book::LocalBook,
+        util::{
+            connect, parse_text, send_json, symbol_map, LoopState, ReadStream, WriteSink, WsStream,
+        },
+        Connector, Resync,
+    },
+    msg::Levels,
+    SETTINGS,
+};
+
+pub struct Okx {
+    // Instrument of every OKX instrument id
+    inst_ids: HashMap<String, &'static str>,
+    books: HashMap<&'static str, InstrumentBook>,
+}
+
+#[derive(Default)]
+struct InstrumentBook {
+    book: LocalBook,
+}
+
+const EXCHANGE: &str = "OKX";
+const OKX_WSS: &str = "wss://ws.okx.com:8443/ws/v5/public";
+
+/// Levels per side covered by the book checksum.
+const CHECKSUM_DEPTH: usize = 25;
+
+impl Okx {
+    pub fn new() -> Okx {
+        Okx {
+            inst_ids: symbol_map(SETTINGS.instruments(EXCHANGE), Self::inst_id),
+            books: HashMap::new(),
+        }
+    }
+
+    /// Maps a `BASE/QUOTE` pair to OKX's `BASE-QUOTE` instrument id.
+    fn inst_id(instrument: &str) -> String {
+        instrument.to_uppercase().replace('/', "-")
+    }
+
+    fn request(&self, op: &str) -> Request {
+        Request {
+            op: op.to_string(),
+            args: self
+                .inst_ids
+                .keys()
+                .map(|inst_id| Arg {
+                    channel: String::from("books"),
+                    inst_id: inst_id.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl InstrumentBook {
+    /// CRC32 over the top bids and asks interleaved as
+    /// `bid price:bid size:ask price:ask size:...`, using the strings OKX sent.
+    /// When one side runs out the remaining levels of the other side follow.
+    fn checksum(&self) -> i32 {
+        let mut bids = self.book.bids().take(CHECKSUM_DEPTH);
+        let mut asks = self.book.asks().take(CHECKSUM_DEPTH);
+
+        let mut fields = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+        loop {
+            let (bid, ask) = (bids.next(), asks.next());
+            if bid.is_none() && ask.is_none() {
+                break;
+            }
+            for entry in [bid, ask].into_iter().flatten() {
+                fields.push(entry.raw_price.as_str());
+                fields.push(entry.raw_amount.as_str());
+            }
+        }
+
+        crc32fast::hash(fields.join(":").as_bytes()) as i32
+    }
+}
+
+#[async_trait]
+impl Connector for Okx {
+    fn name(&self) -> &'static str {
+        EXCHANGE
+    }
+
+    async fn connect(&mut self) -> Result<WsStream, Error> {
+        connect(OKX_WSS).await
+    }
+
+    async fn subscribe(
+        &mut self,
+        write: &mut WriteSink,
+        _read: &mut ReadStream,
+    ) -> Result<(), LoopState> {
+        self.books = self
+            .inst_ids
+            .values()
+            .map(|&instrument| (instrument, InstrumentBook::default()))
+            .collect();
+
+        // Acks come one per instrument and may follow the first snapshots, so
+        // they are checked by `parse` rather than waited for
+        if let Err(err) = send_json(write, &self.request("subscribe")).await {
+            eprintln!("Could not subscribe to OKX: {}", err);
+            return Err(LoopState::Break);
+        }
+
+        Ok(())
+    }
+
+    fn parse(&mut self, text: &str) -> Result<Option<Levels>, Resync> {
+        let message = match parse_text::<Message>(text) {
+            Ok(message) => message,
+            Err(_) => return Ok(None),
+        };
+
+        if message.event.as_deref() == Some("error") {
+            eprintln!("OKX request failed: {}", text);
+            return Ok(None);
+        }
+
+        let instrument = match message.arg.and_then(|arg| self.inst_ids.get(&arg.inst_id)) {
+            Some(&instrument) => instrument,
+            None => return Ok(None),
+        };
+        let local = match self.books.get_mut(instrument) {
+            Some(local) => local,
+            None => return Ok(None),
+        };
+
+        match message.action.as_deref() {
+            Some("snapshot") => local.book.snapshot(),
+            Some("update") if local.book.is_synced() => {}
+            _ => return Ok(None),
+        }
+
+        for book in message.data {
+            for [price, size, _, _] in &book.bids {
+                local.book.update_bid(price, size);
+            }
+            for [price, size, _, _] in &book.asks {
+                local.book.update_ask(price, size);
+            }
+
+            let checksum = local.checksum();
+            if checksum != book.checksum {
+                eprintln!(
+                    "OKX {} checksum mismatch: expected {}, computed {}",
+                    instrument, book.checksum, checksum
+                );
+                return Err(Resync);
+            }
+        }
+
+        Ok(Some(local.book.levels(EXCHANGE, instrument)))
+    }
+
+    async fn unsubscribe(&mut self, write: &mut WriteSink) {
+        if let Err(err) = send_json(write, &self.request("unsubscribe")).await {
+            eprintln!("Could not unsubscribe from OKX: {}", err);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Request {
+    op: String,
+    args: Vec<Arg>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Arg {
+    channel: String,
+    #[serde(rename = "instId")]
+    inst_id: String,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    event: Option<String>,
+    arg: Option<Arg>,
+    action: Option<String>,
+    #[serde(default)]
+    data: Vec<Book>,
+}
+
+/// Levels are `[price, size, deprecated, number of orders]`.
+#[derive(Deserialize)]
+struct Book {
+    bids: Vec<[String; 4]>,
+    asks: Vec<[String; 4]>,
+    checksum: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(bids: &[(&str, &str)], asks: &[(&str, &str)]) -> InstrumentBook {
+        let mut book = InstrumentBook::default();
+        for (price, size) in bids {
+            book.book.update_bid(price, size);
+        }
+        for (price, size) in asks {
+            book.book.update_ask(price, size);
+        }
+        book
+    }
+
+    #[test]
+    fn checksum_interleaves_sides() {
+        // "3366.1:7:3366.8:9:3366:6:3368:8", from OKX's checksum example
+        let book = book(
+            &[("3366.1", "7"), ("3366", "6")],
+            &[("3366.8", "9"), ("3368", "8")],
+        );
+        assert_eq!(book.checksum(), -1881014294);
+    }
+
+    #[test]
+    fn checksum_continues_with_the_longer_side() {
+        // "3366.1:7:3366.8:9:3368:8", from OKX's example with fewer bids than asks
+        let book = book(&[("3366.1", "7")], &[("3366.8", "9"), ("3368", "8")]);
+        assert_eq!(book.checksum(), -1471518219);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::exchange::{
+    binance::Binance, bitstamp::Bitstamp, bybit::Bybit, coinbase::Coinbase, deribit::Deribit,
+    htx::Htx, kraken::Kraken, okx::Okx, Connector,
+};
+
+type Constructor = fn() -> Box<dyn Connector>;
+
+/// Every venue that can be listed in `app.exchanges`, keyed by its settings name.
+const CONNECTORS: &[(&str, Constructor)] = &[
+    ("binance", || Box::new(Binance::new())),
+    ("bitstamp", || Box::new(Bitstamp::new())),
+    ("kraken", || Box::new(Kraken::new())),
+    ("coinbase", || Box::new(Coinbase::new())),
+    ("okx", || Box::new(Okx::new())),
+    ("bybit", || Box::new(Bybit::new())),
+    ("htx", || Box::new(Htx::new())),
+    ("deribit", || Box::new(Deribit::new())),
+];
+
+pub fn create(name: &str) -> Option<Box<dyn Connector>> {
+    CONNECTORS
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, constructor)| constructor())
+}
@@ -1,9 +1,23 @@
-use futures_util::{stream::SplitStream, StreamExt};
-use serde::de::DeserializeOwned;
+use std::{collections::HashMap, io::Read};
+
+use flate2::read::GzDecoder;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
 use tokio::net::TcpStream;
-use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{Error, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::msg::Level;
 
-type ReadStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+pub type WriteSink = SplitSink<WsStream, Message>;
+pub type ReadStream = SplitStream<WsStream>;
 
 #[derive(Debug)]
 pub enum LoopState {
@@ -11,20 +25,29 @@ pub enum LoopState {
     Break,
 }
 
-pub async fn read_from_stream<T>(read: &mut ReadStream) -> Result<T, LoopState>
+pub async fn connect(url: &str) -> Result<WsStream, Error> {
+    let url = url::Url::parse(url).unwrap();
+    let (ws_stream, _) = connect_async(url).await?;
+    Ok(ws_stream)
+}
+
+pub async fn send_text(write: &mut WriteSink, text: String) -> Result<(), Error> {
+    write.send(Message::Text(text)).await
+}
+
+pub async fn send_json<T>(write: &mut WriteSink, request: &T) -> Result<(), Error>
 where
-    T: DeserializeOwned,
+    T: Serialize,
 {
+    send_text(write, serde_json::to_string(request).unwrap()).await
+}
+
+/// Reads the next text, binary or ping frame.
+pub async fn read_frame(read: &mut ReadStream) -> Result<Message, LoopState> {
     if let Some(msg) = read.next().await {
         if let Ok(msg) = msg {
-            if let Message::Text(text) = msg {
-                match serde_json::from_str::<T>(text.as_str()) {
-                    Ok(response) => Ok(response),
-                    Err(err) => {
-                        eprintln!("Error parsing message: {}\n{}", err, text);
-                        Err(LoopState::Continue)
-                    }
-                }
+            if let Message::Text(_) | Message::Binary(_) | Message::Ping(_) = msg {
+                Ok(msg)
             } else {
                 Err(LoopState::Continue)
             }
@@ -37,3 +60,79 @@ where
         Err(LoopState::Break)
     }
 }
+
+pub async fn read_text(read: &mut ReadStream) -> Result<String, LoopState> {
+    if let Message::Text(text) = read_frame(read).await? {
+        Ok(text)
+    } else {
+        Err(LoopState::Continue)
+    }
+}
+
+/// Like `read_text`, but skips over frames that aren't text.
+pub async fn next_text(read: &mut ReadStream) -> Result<String, LoopState> {
+    loop {
+        match read_text(read).await {
+            Err(LoopState::Continue) => continue,
+            res => return res,
+        }
+    }
+}
+
+pub fn gunzip(data: &[u8]) -> Option<String> {
+    let mut text = String::new();
+    match GzDecoder::new(data).read_to_string(&mut text) {
+        Ok(_) => Some(text),
+        Err(err) => {
+            eprintln!("Error decompressing message: {}", err);
+            None
+        }
+    }
+}
+
+pub fn parse_text<T>(text: &str) -> Result<T, LoopState>
+where
+    T: DeserializeOwned,
+{
+    match serde_json::from_str::<T>(text) {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            eprintln!("Error parsing message: {}\n{}", err, text);
+            Err(LoopState::Continue)
+        }
+    }
+}
+
+pub async fn fetch_json<T>(url: &str) -> Result<T, reqwest::Error>
+where
+    T: DeserializeOwned,
+{
+    reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .json::<T>()
+        .await
+}
+
+pub fn parse_levels(exchange: &'static str, levels: Vec<[String; 2]>) -> Vec<Level> {
+    levels
+        .into_iter()
+        .map(|l| Level {
+            exchange,
+            price: l[0].parse().unwrap(),
+            amount: l[1].parse().unwrap(),
+            synthetic: false,
+        })
+        .collect()
+}
+
+/// Maps the symbols a venue knows the instruments by back to the instruments.
+pub fn symbol_map(
+    instruments: &'static [String],
+    symbol: fn(&str) -> String,
+) -> HashMap<String, &'static str> {
+    instruments
+        .iter()
+        .map(|instrument| (symbol(instrument), instrument.as_str()))
+        .collect()
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod exchange;
+pub mod market_data;
+pub mod msg;
+pub mod server;
+pub mod settings;
+pub mod shutdown;
+
+lazy_static! {
+    pub static ref SETTINGS: settings::Settings =
+        settings::Settings::new().expect("settings can't be loaded");
+}
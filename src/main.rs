@@ -1,20 +1,6 @@
-#[macro_use]
-extern crate lazy_static;
-
-mod exchange;
-mod market_data;
-mod msg;
-mod server;
-mod settings;
-mod shutdown;
-
+use combined_ob::{exchange, market_data, msg, server, shutdown, SETTINGS};
 use tokio::sync::{broadcast, mpsc};
 
-lazy_static! {
-    static ref SETTINGS: settings::Settings =
-        settings::Settings::new().expect("settings can't be loaded");
-}
-
 #[tokio::main]
 async fn main() {
     let shutdown_tx = shutdown::Sender::new();
@@ -33,35 +19,31 @@ async fn main() {
 }
 
 fn process_market_data(shutdown_tx: &shutdown::Sender) {
-    let (levels_tx, levels_rx) = mpsc::channel::<msg::Levels>(SETTINGS.app.channel_capacity);
-    let (summary_tx, summary_rx) = broadcast::channel(SETTINGS.app.channel_capacity);
-
-    let levels_tx_clone = levels_tx.clone();
-    let shutdown_rx = shutdown_tx.subscribe();
-    tokio::spawn(async {
-        let mut binance = exchange::Binance {
-            levels_tx: levels_tx_clone,
-            shutdown_rx,
-        };
-        binance.connect().await
-    });
+    let (levels_tx, levels_rx) = mpsc::channel::<msg::Update>(SETTINGS.app.channel_capacity);
+    let (book_tx, book_rx) = broadcast::channel(SETTINGS.app.channel_capacity);
+    let (arbitrage_tx, _) = broadcast::channel(SETTINGS.app.channel_capacity);
+
+    for name in &SETTINGS.app.exchanges {
+        let connector =
+            exchange::create(name).unwrap_or_else(|| panic!("Unknown exchange: {}", name));
+        let levels_tx = levels_tx.clone();
+        let shutdown_rx = shutdown_tx.subscribe();
+        tokio::spawn(exchange::run(connector, levels_tx, shutdown_rx));
+    }
 
-    let shutdown_rx = shutdown_tx.subscribe();
-    tokio::spawn(async {
-        let mut bitstamp = exchange::Bitstamp {
-            levels_tx,
-            shutdown_rx,
-        };
-        bitstamp.connect().await
-    });
+    let cache = market_data::BookCache::default();
 
-    let summary_tx_clone = summary_tx.clone();
+    let book_tx_clone = book_tx.clone();
+    let cache_clone = cache.clone();
+    let arbitrage_tx_clone = arbitrage_tx.clone();
     let shutdown_rx = shutdown_tx.subscribe();
     tokio::spawn(async {
         let mut orderbook = market_data::Orderbook {
             levels_rx,
-            summary_tx: summary_tx_clone,
-            summary_rx,
+            book_tx: book_tx_clone,
+            book_rx,
+            cache: cache_clone,
+            arbitrage_tx: arbitrage_tx_clone,
             shutdown_rx,
         };
         orderbook.aggregate().await
@@ -70,6 +52,6 @@ fn process_market_data(shutdown_tx: &shutdown::Sender) {
     let shutdown_rx = shutdown_tx.subscribe();
     tokio::spawn(async {
         let mut server = server::Server { shutdown_rx };
-        server.serve(summary_tx).await
+        server.serve(book_tx, cache, arbitrage_tx).await
     });
 }
@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rust_decimal::Decimal;
+
+use crate::msg::{self, Arbitrage, ArbitrageKind};
+
+/// Finds prices one exchange bids above another's asks and tracks how long
+/// they stay that way.
+pub struct Detector {
+    fee_adjusted: bool,
+    // Keyed by instrument, buying exchange and selling exchange
+    open: HashMap<(&'static str, &'static str, &'static str), Opportunity>,
+}
+
+struct Opportunity {
+    opened: Instant,
+    buy_price: Decimal,
+    sell_price: Decimal,
+    size: Decimal,
+    profit: Decimal,
+}
+
+/// `(price, amount)` pairs of a single exchange, best first.
+type Side = Vec<(Decimal, Decimal)>;
+
+impl Detector {
+    pub fn new(fee_adjusted: bool) -> Detector {
+        Detector {
+            fee_adjusted,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Compares every exchange's bids with every other exchange's asks,
+    /// returning what changed since the instrument's previous book.
+    pub fn update(&mut self, book: &msg::Book) -> Vec<Arbitrage> {
+        let now = Instant::now();
+        let (bids, asks) = self.sides(book);
+
+        let mut found = HashMap::new();
+        for (&sell_exchange, bids) in &bids {
+            for (&buy_exchange, asks) in &asks {
+                if sell_exchange == buy_exchange {
+                    continue;
+                }
+                if let Some(opportunity) = walk(bids, asks, now) {
+                    found.insert((book.instrument, buy_exchange, sell_exchange), opportunity);
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+
+        let closed: Vec<_> = self
+            .open
+            .keys()
+            .filter(|key| key.0 == book.instrument && !found.contains_key(*key))
+            .copied()
+            .collect();
+        for key in closed {
+            let opportunity = self.open.remove(&key).unwrap();
+            events.push(event(ArbitrageKind::Close, key, &opportunity, now));
+        }
+
+        for (key, mut opportunity) in found {
+            let kind = match self.open.get(&key) {
+                Some(open) => {
+                    opportunity.opened = open.opened;
+                    if open.size == opportunity.size && open.profit == opportunity.profit {
+                        continue;
+                    }
+                    ArbitrageKind::Update
+                }
+                None => ArbitrageKind::Open,
+            };
+
+            events.push(event(kind, key, &opportunity, now));
+            self.open.insert(key, opportunity);
+        }
+
+        events
+    }
+
+    /// Splits the book's levels by exchange, with the prices opportunities are judged by.
+    /// Synthetic levels are left out, hitting them takes a trade on each leg. So are
+    /// bids no ask is below and asks no bid is above, they can't be part of an opportunity.
+    fn sides(
+        &self,
+        book: &msg::Book,
+    ) -> (HashMap<&'static str, Side>, HashMap<&'static str, Side>) {
+        let mut book_bids = book
+            .priced_bids(self.fee_adjusted)
+            .filter(|(_, level)| !level.synthetic)
+            .peekable();
+        let mut book_asks = book
+            .priced_asks(self.fee_adjusted)
+            .filter(|(_, level)| !level.synthetic)
+            .peekable();
+
+        let (best_bid, best_ask) = match (book_bids.peek(), book_asks.peek()) {
+            (Some(&(bid, _)), Some(&(ask, _))) => (bid, ask),
+            _ => return (HashMap::new(), HashMap::new()),
+        };
+
+        let mut bids: HashMap<_, Side> = HashMap::new();
+        let mut asks: HashMap<_, Side> = HashMap::new();
+
+        for (price, level) in book_bids.take_while(|&(price, _)| price > best_ask) {
+            bids.entry(level.exchange)
+                .or_default()
+                .push((price, level.amount));
+        }
+        for (price, level) in book_asks.take_while(|&(price, _)| price < best_bid) {
+            asks.entry(level.exchange)
+                .or_default()
+                .push((price, level.amount));
+        }
+
+        (bids, asks)
+    }
+}
+
+/// Buys from the asks and sells into the bids for as long as that's profitable.
+fn walk(bids: &Side, asks: &Side, now: Instant) -> Option<Opportunity> {
+    let (&(sell_price, _), &(buy_price, _)) = (bids.first()?, asks.first()?);
+    if sell_price <= buy_price {
+        return None;
+    }
+
+    let mut bids = bids.iter().copied();
+    let mut asks = asks.iter().copied();
+    let (mut bid, mut ask) = (bids.next(), asks.next());
+
+    let mut size = Decimal::ZERO;
+    let mut profit = Decimal::ZERO;
+    while let (Some((bid_price, bid_amount)), Some((ask_price, ask_amount))) = (bid, ask) {
+        if bid_price <= ask_price {
+            break;
+        }
+
+        let amount = bid_amount.min(ask_amount);
+        size += amount;
+        profit += amount * (bid_price - ask_price);
+
+        bid = match bid_amount - amount {
+            left if left.is_zero() => bids.next(),
+            left => Some((bid_price, left)),
+        };
+        ask = match ask_amount - amount {
+            left if left.is_zero() => asks.next(),
+            left => Some((ask_price, left)),
+        };
+    }
+
+    Some(Opportunity {
+        opened: now,
+        buy_price,
+        sell_price,
+        size,
+        profit,
+    })
+}
+
+fn event(
+    kind: ArbitrageKind,
+    (instrument, buy_exchange, sell_exchange): (&'static str, &'static str, &'static str),
+    opportunity: &Opportunity,
+    now: Instant,
+) -> Arbitrage {
+    Arbitrage {
+        kind,
+        instrument,
+        buy_exchange,
+        sell_exchange,
+        buy_price: opportunity.buy_price,
+        sell_price: opportunity.sell_price,
+        size: opportunity.size,
+        profit: opportunity.profit,
+        duration: match kind {
+            ArbitrageKind::Open => Duration::ZERO,
+            _ => now.duration_since(opportunity.opened),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{market_data::LevelMap, SETTINGS};
+
+    const INSTRUMENT: &str = "BTC/USDT";
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    /// Levels from `(price, amount)` pairs, best first.
+    fn levels(exchange: &'static str, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> msg::Levels {
+        let side = |levels: &[(&str, &str)]| {
+            levels
+                .iter()
+                .map(|&(price, amount)| msg::Level {
+                    exchange,
+                    price: dec(price),
+                    amount: dec(amount),
+                    synthetic: false,
+                })
+                .collect()
+        };
+        msg::Levels {
+            exchange,
+            instrument: INSTRUMENT,
+            bids: side(bids),
+            asks: side(asks),
+        }
+    }
+
+    fn level_map() -> LevelMap {
+        LevelMap::new(INSTRUMENT, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn walks_a_partial_cross_over_several_levels() {
+        let mut map = level_map();
+        map.update(
+            levels(
+                "binance",
+                &[("98", "1")],
+                &[("100", "1"), ("101", "1"), ("105", "5")],
+            ),
+            false,
+        );
+        map.update(
+            levels(
+                "kraken",
+                &[("103", "1.5"), ("102", "1"), ("99", "2")],
+                &[("106", "1")],
+            ),
+            false,
+        );
+
+        let events = Detector::new(false).update(&map.book());
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.kind, ArbitrageKind::Open);
+        assert_eq!(
+            (event.buy_exchange, event.sell_exchange),
+            ("binance", "kraken")
+        );
+        assert_eq!(
+            (event.buy_price, event.sell_price),
+            (dec("100"), dec("103"))
+        );
+        // 1 at 100 against 103, then 0.5 at 101 against 103 and 0.5 at 101
+        // against 102, the rest of the 102 bid finding no ask below it
+        assert_eq!(event.size, dec("2"));
+        assert_eq!(event.profit, dec("4.5"));
+        assert_eq!(event.duration, Duration::ZERO);
+    }
+
+    #[test]
+    fn fees_are_taken_from_both_sides() {
+        let mut map = level_map();
+        map.update(levels("binance", &[("99", "1")], &[("100", "1")]), false);
+        map.update(levels("kraken", &[("101", "1")], &[("102", "1")]), false);
+        let book = map.book();
+
+        let events = Detector::new(true).update(&book);
+
+        let buy_price = dec("100") * (Decimal::ONE + SETTINGS.fees.taker_fee("binance"));
+        let sell_price = dec("101") * (Decimal::ONE - SETTINGS.fees.taker_fee("kraken"));
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].buy_price, events[0].sell_price),
+            (buy_price, sell_price)
+        );
+        assert_eq!(events[0].profit, sell_price - buy_price);
+
+        // A cross narrower than the fees is no opportunity once they're paid
+        let mut map = level_map();
+        map.update(levels("binance", &[("99", "1")], &[("100", "1")]), false);
+        map.update(levels("kraken", &[("100.01", "1")], &[("102", "1")]), false);
+        assert_eq!(Detector::new(false).update(&map.book()).len(), 1);
+        assert!(Detector::new(true).update(&map.book()).is_empty());
+    }
+
+    #[test]
+    fn updates_and_closes_keep_when_the_opportunity_opened() {
+        let mut detector = Detector::new(false);
+        let mut map = level_map();
+        map.update(levels("binance", &[("98", "1")], &[("100", "2")]), false);
+        map.update(levels("kraken", &[("101", "1")], &[("106", "1")]), false);
+        assert_eq!(detector.update(&map.book())[0].kind, ArbitrageKind::Open);
+
+        // An unchanged opportunity isn't reported again
+        assert!(detector.update(&map.book()).is_empty());
+
+        thread::sleep(Duration::from_millis(10));
+        map.update(levels("kraken", &[("101", "1.5")], &[("106", "1")]), false);
+        let events = detector.update(&map.book());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, ArbitrageKind::Update);
+        assert_eq!(events[0].size, dec("1.5"));
+        assert_eq!(events[0].profit, dec("1.5"));
+        let updated_after = events[0].duration;
+        assert!(updated_after >= Duration::from_millis(10));
+
+        thread::sleep(Duration::from_millis(10));
+        map.update(levels("kraken", &[("99", "1.5")], &[("106", "1")]), false);
+        let events = detector.update(&map.book());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, ArbitrageKind::Close);
+        assert_eq!(
+            (events[0].buy_exchange, events[0].sell_exchange),
+            ("binance", "kraken")
+        );
+        assert!(events[0].duration >= updated_after + Duration::from_millis(10));
+    }
+}
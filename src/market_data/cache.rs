@@ -0,0 +1,22 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::msg;
+
+/// Latest book of every instrument, so clients don't have to wait for the next update.
+#[derive(Clone, Default)]
+pub struct BookCache {
+    books: Arc<RwLock<HashMap<&'static str, Arc<msg::Book>>>>,
+}
+
+impl BookCache {
+    pub fn get(&self, instrument: &str) -> Option<Arc<msg::Book>> {
+        self.books.read().unwrap().get(instrument).cloned()
+    }
+
+    pub fn insert(&self, book: Arc<msg::Book>) {
+        self.books.write().unwrap().insert(book.instrument, book);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::{msg, SETTINGS};
+
+/// Converts the levels venues stream in another quote currency into the
+/// configured instruments they stand in for.
+pub struct Converter {
+    // Keyed by the venue's lowercase name and the instrument it streams
+    targets: HashMap<(String, &'static str), Target>,
+}
+
+struct Target {
+    instrument: &'static str,
+    rate: Rate,
+    price_precision: u32,
+}
+
+enum Rate {
+    Fixed(Decimal),
+    /// The mid of a book, dividing prices when the book is quoted in the
+    /// venue's currency rather than multiplying them.
+    Book {
+        instrument: &'static str,
+        divide: bool,
+    },
+}
+
+/// `BASE/QUOTE` split into its currencies.
+fn currencies(instrument: &str) -> (&str, &str) {
+    instrument
+        .split_once('/')
+        .unwrap_or_else(|| panic!("Instrument {} isn't written BASE/QUOTE", instrument))
+}
+
+impl Converter {
+    /// The conversions in the settings, panicking when one can't be applied.
+    pub fn configured() -> Converter {
+        let mut targets = HashMap::new();
+
+        for (venue, conversion) in &SETTINGS.conversion {
+            for (instrument, streamed) in &conversion.instruments {
+                let instrument = SETTINGS
+                    .app
+                    .instruments
+                    .iter()
+                    .find(|configured| *configured == instrument)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "{} converts {}, which isn't in app.instruments",
+                            venue, instrument
+                        )
+                    });
+
+                let (base, quote) = currencies(instrument);
+                let (streamed_base, venue_quote) = currencies(streamed);
+                if base != streamed_base {
+                    panic!(
+                        "{} can't stand in for {} on {}",
+                        streamed, instrument, venue
+                    );
+                }
+
+                let rate = match (&conversion.rate_instrument, conversion.fixed_rate) {
+                    (Some(rate_instrument), _) => Rate::Book {
+                        instrument: rate_instrument,
+                        divide: match currencies(rate_instrument) {
+                            (from, to) if from == venue_quote && to == quote => false,
+                            (from, to) if from == quote && to == venue_quote => true,
+                            _ => panic!(
+                                "{} doesn't convert {} into {} on {}",
+                                rate_instrument, venue_quote, quote, venue
+                            ),
+                        },
+                    },
+                    (None, Some(rate)) => Rate::Fixed(rate),
+                    (None, None) => panic!("No conversion rate configured for {}", venue),
+                };
+
+                targets.insert(
+                    (venue.to_lowercase(), streamed.as_str()),
+                    Target {
+                        instrument,
+                        rate,
+                        price_precision: conversion.price_precision,
+                    },
+                );
+            }
+        }
+
+        Converter { targets }
+    }
+
+    /// The configured instrument the venue's `instrument` stands in for, or
+    /// `instrument` itself when it isn't converted.
+    pub fn instrument(&self, exchange: &str, instrument: &'static str) -> &'static str {
+        self.targets
+            .get(&(exchange.to_lowercase(), instrument))
+            .map_or(instrument, |target| target.instrument)
+    }
+
+    /// The levels in the quote currency of the instrument they stand in for,
+    /// or `None` while the rate book has no mid. Levels of other instruments
+    /// are returned as they are.
+    ///
+    /// Prices are converted at the rate of the moment and rounded to the
+    /// conversion's precision, levels landing on the same price merged. They
+    /// keep that rate until the venue's next update converts them again.
+    pub fn convert(
+        &self,
+        mut levels: msg::Levels,
+        mid: impl Fn(&str) -> Option<Decimal>,
+    ) -> Option<msg::Levels> {
+        let target = match self
+            .targets
+            .get(&(levels.exchange.to_lowercase(), levels.instrument))
+        {
+            Some(target) => target,
+            None => return Some(levels),
+        };
+        levels.instrument = target.instrument;
+
+        if levels.is_empty() {
+            return Some(levels);
+        }
+
+        let (rate, divide) = match target.rate {
+            Rate::Fixed(rate) => (rate, false),
+            Rate::Book { instrument, divide } => {
+                (mid(instrument).filter(|mid| *mid > Decimal::ZERO)?, divide)
+            }
+        };
+        let convert = |price: Decimal| {
+            let price = if divide { price / rate } else { price * rate };
+            price.round_dp(target.price_precision).normalize()
+        };
+
+        levels.bids = merged(levels.bids, convert);
+        levels.asks = merged(levels.asks, convert);
+        Some(levels)
+    }
+}
+
+/// Converts the prices of a side, summing the amounts of consecutive levels
+/// the conversion gives the same price so the side stays strictly ordered.
+fn merged(side: Vec<msg::Level>, convert: impl Fn(Decimal) -> Decimal) -> Vec<msg::Level> {
+    let mut merged: Vec<msg::Level> = Vec::with_capacity(side.len());
+    for mut level in side {
+        level.price = convert(level.price);
+        match merged.last_mut() {
+            Some(last) if last.price == level.price => last.amount += level.amount,
+            _ => merged.push(level),
+        }
+    }
+    merged
+}
@@ -0,0 +1,389 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+
+use rust_decimal::Decimal;
+use tokio::time::Instant;
+
+use crate::{msg, SETTINGS};
+
+struct Venue {
+    levels: msg::Levels,
+    updated: Instant,
+}
+
+fn insert<K: Ord + Clone>(ladder: &mut msg::Ladder<K>, key: K, level: &msg::Level) {
+    ladder.insert((key, level.exchange, level.synthetic), *level);
+}
+
+fn remove<K: Ord + Clone>(ladder: &mut msg::Ladder<K>, key: K, level: &msg::Level) {
+    ladder.remove(&(key, level.exchange, level.synthetic));
+}
+
+/// Moves one side of an exchange's levels from `old` to `new`, walking both
+/// best first so only the levels that changed touch the ladders. Relies on
+/// both being ordered as `msg::Levels` promises.
+fn replace<K: Ord + Clone>(
+    ladder: &mut msg::Ladder<K>,
+    fee_adjusted: &mut msg::Ladder<K>,
+    old: &[msg::Level],
+    new: &[msg::Level],
+    key: impl Fn(Decimal) -> K,
+    fee_factor: Decimal,
+) {
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        match (old.peek(), new.peek()) {
+            (Some(o), Some(n)) if o.price == n.price => {
+                if o.amount != n.amount {
+                    insert(ladder, key(n.price), n);
+                    insert(fee_adjusted, key(after_fee(n, fee_factor)), n);
+                }
+                old.next();
+                new.next();
+            }
+            (Some(o), n) if n.is_none_or(|n| key(o.price) < key(n.price)) => {
+                remove(ladder, key(o.price), o);
+                remove(fee_adjusted, key(after_fee(o, fee_factor)), o);
+                old.next();
+            }
+            (_, Some(n)) => {
+                insert(ladder, key(n.price), n);
+                insert(fee_adjusted, key(after_fee(n, fee_factor)), n);
+                new.next();
+            }
+            _ => break,
+        }
+    }
+}
+
+/// The levels of every exchange for an instrument, kept sorted as each
+/// exchange replaces its levels rather than sorted for every book.
+pub struct LevelMap {
+    instrument: &'static str,
+    // Keyed by exchange and whether the levels are synthetic
+    exchange_map: HashMap<(&'static str, bool), Venue>,
+    // Exchanges whose levels expired, until they publish again
+    stale: BTreeSet<(&'static str, bool)>,
+    max_age: Duration,
+    bids: msg::Ladder<Reverse<Decimal>>,
+    asks: msg::Ladder<Decimal>,
+    // Keyed by the prices after taker fees
+    fee_adjusted_bids: msg::Ladder<Reverse<Decimal>>,
+    fee_adjusted_asks: msg::Ladder<Decimal>,
+}
+
+/// Taking liquidity costs the fee, so it lowers what a bid is worth and raises
+/// what an ask costs. Synthetic levels take a trade on each leg, paying it twice.
+fn after_fee(level: &msg::Level, factor: Decimal) -> Decimal {
+    if level.synthetic {
+        level.price * factor * factor
+    } else {
+        level.price * factor
+    }
+}
+
+impl LevelMap {
+    pub fn new(instrument: &'static str, max_age: Duration) -> LevelMap {
+        LevelMap {
+            instrument,
+            exchange_map: HashMap::new(),
+            stale: BTreeSet::new(),
+            max_age,
+            bids: msg::Ladder::new(),
+            asks: msg::Ladder::new(),
+            fee_adjusted_bids: msg::Ladder::new(),
+            fee_adjusted_asks: msg::Ladder::new(),
+        }
+    }
+
+    /// Replaces the levels the exchange published before, its synthetic ones
+    /// when `synthetic` is set.
+    pub fn update(&mut self, levels: msg::Levels, synthetic: bool) {
+        debug_assert!(
+            levels.is_ordered(),
+            "{} {} levels aren't best first with unique prices",
+            levels.exchange,
+            levels.instrument
+        );
+
+        let key = (levels.exchange, synthetic);
+        self.stale.remove(&key);
+
+        let empty = msg::Levels::empty(levels.exchange, self.instrument);
+        let old = self.exchange_map.remove(&key);
+        self.replace(old.as_ref().map_or(&empty, |venue| &venue.levels), &levels);
+
+        if !levels.is_empty() {
+            let venue = Venue {
+                levels,
+                updated: Instant::now(),
+            };
+            self.exchange_map.insert(key, venue);
+        }
+    }
+
+    fn replace(&mut self, old: &msg::Levels, new: &msg::Levels) {
+        let fee = SETTINGS.fees.taker_fee(new.exchange);
+        replace(
+            &mut self.bids,
+            &mut self.fee_adjusted_bids,
+            &old.bids,
+            &new.bids,
+            Reverse,
+            Decimal::ONE - fee,
+        );
+        replace(
+            &mut self.asks,
+            &mut self.fee_adjusted_asks,
+            &old.asks,
+            &new.asks,
+            |price| price,
+            Decimal::ONE + fee,
+        );
+    }
+
+    /// Marks the exchange's levels, direct and synthetic, as current without
+    /// changing them.
+    pub fn touch(&mut self, exchange: &'static str, now: Instant) {
+        for synthetic in [false, true] {
+            if let Some(venue) = self.exchange_map.get_mut(&(exchange, synthetic)) {
+                venue.updated = now;
+            }
+        }
+    }
+
+    /// The levels an exchange quotes directly.
+    pub fn levels(&self, exchange: &'static str) -> Option<&msg::Levels> {
+        self.exchange_map
+            .get(&(exchange, false))
+            .map(|venue| &venue.levels)
+    }
+
+    /// Midpoint of the best bid and ask across exchanges.
+    pub fn mid(&self) -> Option<Decimal> {
+        let (bid, ask) = (self.bids.values().next()?, self.asks.values().next()?);
+        Some((bid.price + ask.price) / Decimal::TWO)
+    }
+
+    /// Drops the levels of exchanges that haven't published within `max_age`,
+    /// returning whether any were dropped.
+    pub fn evict_stale(&mut self, now: Instant) -> bool {
+        let expired: Vec<_> = self
+            .exchange_map
+            .iter()
+            .filter(|(_, venue)| now.duration_since(venue.updated) >= self.max_age)
+            .map(|(&key, _)| key)
+            .collect();
+
+        for key in &expired {
+            let (exchange, synthetic) = *key;
+            eprintln!(
+                "{}{} levels older than {:?}, excluding them",
+                exchange,
+                if synthetic { " synthetic" } else { "" },
+                self.max_age
+            );
+            if let Some(venue) = self.exchange_map.remove(key) {
+                let empty = msg::Levels::empty(exchange, self.instrument);
+                self.replace(&venue.levels, &empty);
+            }
+            self.stale.insert(*key);
+        }
+
+        !expired.is_empty()
+    }
+
+    /// When the levels published the longest time ago expire.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.exchange_map
+            .values()
+            .map(|venue| venue.updated + self.max_age)
+            .min()
+    }
+
+    /// The merged book, sharing the ladders rather than copying them.
+    pub fn book(&self) -> msg::Book {
+        msg::Book {
+            instrument: self.instrument,
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+            fee_adjusted_bids: self.fee_adjusted_bids.clone(),
+            fee_adjusted_asks: self.fee_adjusted_asks.clone(),
+            excluded_exchanges: self
+                .stale
+                .iter()
+                .map(|&(exchange, _)| exchange)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Venues = HashMap<(&'static str, bool), (Vec<msg::Level>, Vec<msg::Level>)>;
+
+    const INSTRUMENT: &str = "BTC/USDT";
+
+    /// Levels from `(price, amount)` pairs in hundredths.
+    fn levels(
+        exchange: &'static str,
+        synthetic: bool,
+        bids: &[(i64, i64)],
+        asks: &[(i64, i64)],
+    ) -> msg::Levels {
+        let side = |levels: &[(i64, i64)]| {
+            levels
+                .iter()
+                .map(|&(price, amount)| msg::Level {
+                    exchange,
+                    price: Decimal::new(price, 2),
+                    amount: Decimal::new(amount, 2),
+                    synthetic,
+                })
+                .collect()
+        };
+        msg::Levels {
+            exchange,
+            instrument: INSTRUMENT,
+            bids: side(bids),
+            asks: side(asks),
+        }
+    }
+
+    fn update(map: &mut LevelMap, venues: &mut Venues, levels: msg::Levels, synthetic: bool) {
+        let key = (levels.exchange, synthetic);
+        if levels.is_empty() {
+            venues.remove(&key);
+        } else {
+            venues.insert(key, (levels.bids.clone(), levels.asks.clone()));
+        }
+        map.update(levels, synthetic);
+    }
+
+    fn keys<'a>(
+        levels: impl Iterator<Item = (Decimal, &'a msg::Level)>,
+    ) -> Vec<(Decimal, &'static str, bool, Decimal)> {
+        levels
+            .map(|(price, level)| (price, level.exchange, level.synthetic, level.amount))
+            .collect()
+    }
+
+    /// Compares the incrementally kept book with every venue's levels sorted from scratch.
+    fn assert_matches_resort(map: &LevelMap, venues: &Venues) {
+        let fee = |level: &msg::Level| SETTINGS.fees.taker_fee(level.exchange);
+        let order = |level: &msg::Level| (level.exchange, level.synthetic);
+
+        let mut bids: Vec<_> = venues
+            .values()
+            .flat_map(|(bids, _)| bids.iter().map(|bid| (bid.price, bid)))
+            .collect();
+        bids.sort_by_key(|(price, bid)| (Reverse(*price), order(bid)));
+        let mut asks: Vec<_> = venues
+            .values()
+            .flat_map(|(_, asks)| asks.iter().map(|ask| (ask.price, ask)))
+            .collect();
+        asks.sort_by_key(|(price, ask)| (*price, order(ask)));
+
+        let mut fee_adjusted_bids: Vec<_> = bids
+            .iter()
+            .map(|&(_, bid)| (after_fee(bid, Decimal::ONE - fee(bid)), bid))
+            .collect();
+        fee_adjusted_bids.sort_by_key(|(price, bid)| (Reverse(*price), order(bid)));
+        let mut fee_adjusted_asks: Vec<_> = asks
+            .iter()
+            .map(|&(_, ask)| (after_fee(ask, Decimal::ONE + fee(ask)), ask))
+            .collect();
+        fee_adjusted_asks.sort_by_key(|(price, ask)| (*price, order(ask)));
+
+        let book = map.book();
+        assert_eq!(keys(book.priced_bids(false)), keys(bids.into_iter()));
+        assert_eq!(keys(book.priced_asks(false)), keys(asks.into_iter()));
+        assert_eq!(
+            keys(book.priced_bids(true)),
+            keys(fee_adjusted_bids.into_iter())
+        );
+        assert_eq!(
+            keys(book.priced_asks(true)),
+            keys(fee_adjusted_asks.into_iter())
+        );
+    }
+
+    #[test]
+    fn book_matches_full_resort() {
+        let max_age = Duration::from_secs(60);
+        let mut map = LevelMap::new(INSTRUMENT, max_age);
+        let mut venues = Venues::new();
+
+        let steps = [
+            // Added
+            (
+                levels(
+                    "binance",
+                    false,
+                    &[(10000, 100), (9900, 200), (9800, 300)],
+                    &[(10100, 100), (10200, 200)],
+                ),
+                false,
+            ),
+            (
+                levels(
+                    "kraken",
+                    false,
+                    &[(10000, 50), (9700, 60)],
+                    &[(10150, 70), (10300, 80)],
+                ),
+                false,
+            ),
+            // Amounts changed, levels inserted between and dropped
+            (
+                levels(
+                    "binance",
+                    false,
+                    &[(10000, 150), (9850, 10), (9800, 300)],
+                    &[(10100, 100), (10250, 20)],
+                ),
+                false,
+            ),
+            // Synthetic levels of an exchange quoting the same prices directly
+            (
+                levels("binance", true, &[(10000, 5)], &[(10100, 5), (10400, 5)]),
+                true,
+            ),
+            // Best levels removed
+            (
+                levels("kraken", false, &[(9700, 60)], &[(10300, 80)]),
+                false,
+            ),
+            // Cleared
+            (levels("binance", false, &[], &[]), false),
+            (
+                levels("binance", false, &[(9900, 100)], &[(10000, 100)]),
+                false,
+            ),
+        ];
+
+        for (levels, synthetic) in steps {
+            update(&mut map, &mut venues, levels, synthetic);
+            assert_matches_resort(&map, &venues);
+        }
+
+        assert!(map.evict_stale(Instant::now() + max_age));
+        venues.clear();
+        assert_matches_resort(&map, &venues);
+        assert!(map.book().bids.is_empty() && map.book().asks.is_empty());
+    }
+
+    #[test]
+    fn ordered_levels() {
+        assert!(levels("binance", false, &[(2, 1), (1, 1)], &[(3, 1), (4, 1)]).is_ordered());
+        assert!(!levels("binance", false, &[(1, 1), (2, 1)], &[]).is_ordered());
+        assert!(!levels("binance", false, &[], &[(3, 1), (3, 1)]).is_ordered());
+    }
+}
@@ -1,2 +1,14 @@
+mod arbitrage;
+
+mod cache;
+pub use cache::BookCache;
+
+mod conversion;
+
+mod level_map;
+pub use level_map::LevelMap;
+
 mod orderbook;
 pub use orderbook::Orderbook;
+
+mod synthetic;
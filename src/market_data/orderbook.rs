@@ -1,90 +1,134 @@
-use std::{cmp::Reverse, collections::HashMap};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use float_ord::FloatOrd;
-use tokio::sync::{broadcast, mpsc};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::{self, Instant},
+};
 
-use crate::{msg, server, shutdown, SETTINGS};
+use super::{
+    arbitrage::Detector, conversion::Converter, synthetic::Synthetic, BookCache, LevelMap,
+};
+use crate::{msg, shutdown, SETTINGS};
 
 pub struct Orderbook {
-    pub levels_rx: mpsc::Receiver<msg::Levels>,
-    pub summary_tx: broadcast::Sender<server::orderbook::Summary>,
-    pub summary_rx: broadcast::Receiver<server::orderbook::Summary>,
+    pub levels_rx: mpsc::Receiver<msg::Update>,
+    pub book_tx: broadcast::Sender<Arc<msg::Book>>,
+    // Never read, keeps `book_tx.send` from failing while no client is connected
+    #[allow(dead_code)]
+    pub book_rx: broadcast::Receiver<Arc<msg::Book>>,
+    pub cache: BookCache,
+    pub arbitrage_tx: broadcast::Sender<msg::Arbitrage>,
     pub shutdown_rx: shutdown::Receiver,
 }
 
 impl Orderbook {
     pub async fn aggregate(&mut self) {
-        let mut map = LevelMap::new();
+        let max_age = Duration::from_millis(SETTINGS.app.max_level_age_ms);
+
+        // Books for instruments only some venues stream, such as Deribit's, are
+        // created when their first levels arrive
+        let mut books: HashMap<&'static str, LevelMap> = SETTINGS
+            .app
+            .instruments
+            .iter()
+            .map(|instrument| (instrument.as_str(), LevelMap::new(instrument, max_age)))
+            .collect();
+
+        let converter = Converter::configured();
+        let synthetics = Synthetic::configured();
+        let mut detector = Detector::new(SETTINGS.arbitrage.fee_adjusted);
 
-        loop {
-            tokio::select! {
+        'aggregate: loop {
+            let expiry = books.values().filter_map(LevelMap::next_expiry).min();
+
+            let updated: Vec<&'static str> = tokio::select! {
                 msg = self.levels_rx.recv() => {
                     match msg {
-                        Some(levels) => {
-                            let summary = map.update(levels);
-
-                            if let Err(err) = self.summary_tx.send(summary) {
-                                eprintln!("Unable to send summary: {}", err);
-                                break;
+                        Some(msg::Update::Levels(levels)) => {
+                            // Levels waiting on a conversion rate are dropped, the venue's
+                            // next update replaces them anyway
+                            match converter.convert(levels, |instrument| {
+                                books.get(instrument).and_then(LevelMap::mid)
+                            }) {
+                                Some(levels) => update(&mut books, &synthetics, levels, max_age),
+                                None => Vec::new(),
+                            }
+                        }
+                        Some(msg::Update::Alive { exchange, instruments }) => {
+                            let now = Instant::now();
+                            for instrument in instruments {
+                                let instrument = converter.instrument(exchange, instrument);
+                                if let Some(map) = books.get_mut(instrument) {
+                                    map.touch(exchange, now);
+                                }
+                            }
+                            for synthetic in &synthetics {
+                                if let Some(map) = books.get_mut(synthetic.instrument) {
+                                    map.touch(exchange, now);
+                                }
                             }
+                            Vec::new()
                         }
                         None => break,
                     }
                 },
+                _ = async { time::sleep_until(expiry.unwrap()).await }, if expiry.is_some() => {
+                    let now = Instant::now();
+                    books
+                        .iter_mut()
+                        .filter_map(|(&instrument, map)| map.evict_stale(now).then_some(instrument))
+                        .collect()
+                },
                 _ = self.shutdown_rx.recv() => break,
+            };
+
+            for instrument in updated {
+                let book = Arc::new(books[instrument].book());
+                self.cache.insert(book.clone());
+
+                for arbitrage in detector.update(&book) {
+                    // Only fails while no client is watching for opportunities
+                    let _ = self.arbitrage_tx.send(arbitrage);
+                }
+
+                if let Err(err) = self.book_tx.send(book) {
+                    eprintln!("Unable to send book: {}", err);
+                    break 'aggregate;
+                }
             }
         }
         println!("Exiting orderbook...");
     }
 }
 
-struct LevelMap {
-    exchange_map: HashMap<&'static str, msg::Levels>,
-}
-
-impl LevelMap {
-    fn new() -> LevelMap {
-        LevelMap {
-            exchange_map: HashMap::new(),
-        }
-    }
-
-    fn update(&mut self, levels: msg::Levels) -> server::orderbook::Summary {
-        self.exchange_map.insert(levels.exchange, levels);
-
-        let mut bids: Vec<_> = self
-            .exchange_map
-            .values()
-            .flat_map(|levels| &levels.bids)
-            .collect();
-
-        let mut asks: Vec<_> = self
-            .exchange_map
-            .values()
-            .flat_map(|levels| &levels.asks)
-            .collect();
-
-        bids.sort_unstable_by_key(|bid| Reverse(FloatOrd(bid.price)));
-        asks.sort_unstable_by_key(|ask| FloatOrd(ask.price));
-
-        let bids: Vec<server::orderbook::Level> = bids
-            .into_iter()
-            .take(SETTINGS.app.summary_size)
-            .map(|s| s.into())
-            .collect();
-
-        let asks: Vec<server::orderbook::Level> = asks
-            .into_iter()
-            .take(SETTINGS.app.summary_size)
-            .map(|s| s.into())
-            .collect();
-
-        let spread = if let (Some(best_bid), Some(best_ask)) = (bids.first(), asks.first()) {
-            best_ask.price - best_bid.price
-        } else {
-            0.0
-        };
-
-        server::orderbook::Summary { spread, bids, asks }
+/// Applies an exchange's levels and recomputes the synthetic books using them
+/// as a leg, returning the instruments whose books changed.
+fn update(
+    books: &mut HashMap<&'static str, LevelMap>,
+    synthetics: &[Synthetic],
+    levels: msg::Levels,
+    max_age: Duration,
+) -> Vec<&'static str> {
+    let (exchange, instrument) = (levels.exchange, levels.instrument);
+    let now = Instant::now();
+
+    let map = books
+        .entry(instrument)
+        .or_insert_with(|| LevelMap::new(instrument, max_age));
+    map.update(levels, false);
+    map.evict_stale(now);
+
+    let mut updated = vec![instrument];
+    for synthetic in synthetics.iter().filter(|s| s.depends_on(instrument)) {
+        let leg = |leg| books.get(leg).and_then(|map| map.levels(exchange));
+        let levels = synthetic.levels(exchange, leg(synthetic.base), leg(synthetic.quote));
+
+        let map = books
+            .entry(synthetic.instrument)
+            .or_insert_with(|| LevelMap::new(synthetic.instrument, max_age));
+        map.update(levels, true);
+        map.evict_stale(now);
+        updated.push(synthetic.instrument);
     }
+    updated
 }
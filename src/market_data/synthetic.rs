@@ -0,0 +1,177 @@
+use rust_decimal::Decimal;
+
+use crate::{msg, SETTINGS};
+
+/// An instrument implied on each exchange from its books of two legs quoted in
+/// the same currency.
+pub struct Synthetic {
+    pub instrument: &'static str,
+    pub base: &'static str,
+    pub quote: &'static str,
+}
+
+impl Synthetic {
+    /// The synthetic instruments in the settings, panicking when a leg isn't
+    /// one of the streamed instruments.
+    pub fn configured() -> Vec<Synthetic> {
+        let leg = |instrument: &str, leg: &str| -> &'static str {
+            SETTINGS
+                .app
+                .instruments
+                .iter()
+                .find(|streamed| *streamed == leg)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Leg {} of synthetic {} isn't in app.instruments",
+                        leg, instrument
+                    )
+                })
+        };
+
+        SETTINGS
+            .synthetic
+            .iter()
+            .map(|(instrument, legs)| Synthetic {
+                instrument,
+                base: leg(instrument, &legs.base),
+                quote: leg(instrument, &legs.quote),
+            })
+            .collect()
+    }
+
+    pub fn depends_on(&self, instrument: &str) -> bool {
+        self.base == instrument || self.quote == instrument
+    }
+
+    /// The exchange's implied levels, empty unless it has levels for both legs.
+    pub fn levels(
+        &self,
+        exchange: &'static str,
+        base: Option<&msg::Levels>,
+        quote: Option<&msg::Levels>,
+    ) -> msg::Levels {
+        let (base, quote) = match (base, quote) {
+            (Some(base), Some(quote)) => (base, quote),
+            _ => return msg::Levels::empty(exchange, self.instrument),
+        };
+
+        msg::Levels {
+            exchange,
+            instrument: self.instrument,
+            // Selling the base leg and buying the quote leg with the proceeds
+            bids: implied(exchange, &base.bids, &quote.asks),
+            // Selling the quote leg and buying the base leg with the proceeds
+            asks: implied(exchange, &base.asks, &quote.bids),
+        }
+    }
+}
+
+/// Pairs the levels of both legs best first. Each implied level is priced at
+/// the ratio of the two leg prices and holds as much of the base currency as
+/// the smaller of the two can trade, its amount in the quote leg converted
+/// through that price.
+fn implied(exchange: &'static str, base: &[msg::Level], quote: &[msg::Level]) -> Vec<msg::Level> {
+    let mut levels = Vec::new();
+    let (mut base, mut quote) = (base.iter(), quote.iter());
+    let (mut base_level, mut quote_level) = (base.next(), quote.next());
+    let (mut base_left, mut quote_left) = (
+        base_level.map_or(Decimal::ZERO, |level| level.amount),
+        quote_level.map_or(Decimal::ZERO, |level| level.amount),
+    );
+
+    while let (Some(b), Some(q)) = (base_level, quote_level) {
+        if q.price.is_zero() {
+            break;
+        }
+        let price = b.price / q.price;
+        let quote_amount = quote_left / price;
+
+        // Whichever leg runs out is set to zero rather than decremented, so
+        // rounding can't leave dust behind
+        let amount = if base_left < quote_amount {
+            quote_left -= base_left * price;
+            std::mem::take(&mut base_left)
+        } else {
+            base_left -= quote_amount;
+            quote_left = Decimal::ZERO;
+            quote_amount
+        };
+
+        if amount > Decimal::ZERO {
+            levels.push(msg::Level {
+                exchange,
+                price: price.normalize(),
+                amount: amount.normalize(),
+                synthetic: true,
+            });
+        }
+
+        if base_left <= Decimal::ZERO {
+            base_level = base.next();
+            base_left = base_level.map_or(Decimal::ZERO, |level| level.amount);
+        }
+        if quote_left <= Decimal::ZERO {
+            quote_level = quote.next();
+            quote_left = quote_level.map_or(Decimal::ZERO, |level| level.amount);
+        }
+    }
+
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(levels: &[(&str, &str)]) -> Vec<msg::Level> {
+        levels
+            .iter()
+            .map(|(price, amount)| msg::Level {
+                exchange: "binance",
+                price: price.parse().unwrap(),
+                amount: amount.parse().unwrap(),
+                synthetic: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn implied_carries_what_is_left_of_either_leg() {
+        // ETH/USDT bids against BTC/USDT asks, implying ETH/BTC bids
+        let base = levels(&[("2000", "1"), ("1600", "2")]);
+        let quote = levels(&[("40000", "0.1"), ("50000", "1")]);
+
+        let implied: Vec<_> = implied("binance", &base, &quote)
+            .iter()
+            .map(|level| {
+                (
+                    level.price.to_string(),
+                    level.amount.to_string(),
+                    level.synthetic,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            implied,
+            [
+                // The first base level runs out, leaving 0.05 BTC of the first quote level
+                ("0.05".to_string(), "1".to_string(), true),
+                // Which runs out next, leaving 0.75 ETH of the second base level
+                ("0.04".to_string(), "1.25".to_string(), true),
+                ("0.032".to_string(), "0.75".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn implied_stops_with_the_shorter_leg() {
+        let base = levels(&[("2000", "1")]);
+        let quote = levels(&[("40000", "1"), ("50000", "1")]);
+
+        let implied = implied("binance", &base, &quote);
+
+        assert_eq!(implied.len(), 1);
+        assert_eq!(implied[0].amount, Decimal::ONE);
+    }
+}
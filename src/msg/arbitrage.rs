@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::server::{self, orderbook::arbitrage_event::Kind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArbitrageKind {
+    Open,
+    Update,
+    Close,
+}
+
+/// A change to an opportunity to buy on one exchange and sell on another at a profit.
+#[derive(Debug, Clone)]
+pub struct Arbitrage {
+    pub kind: ArbitrageKind,
+    pub instrument: &'static str,
+    pub buy_exchange: &'static str,
+    pub sell_exchange: &'static str,
+    /// Best ask on the buying exchange, after its fee when fees are taken into account.
+    pub buy_price: Decimal,
+    /// Best bid on the selling exchange, after its fee when fees are taken into account.
+    pub sell_price: Decimal,
+    /// Amount that can be bought and sold at a profit.
+    pub size: Decimal,
+    /// Profit in the quote currency of trading `size`.
+    pub profit: Decimal,
+    /// How long the opportunity has been open.
+    pub duration: Duration,
+}
+
+impl From<&Arbitrage> for server::orderbook::ArbitrageEvent {
+    fn from(arbitrage: &Arbitrage) -> Self {
+        let kind = match arbitrage.kind {
+            ArbitrageKind::Open => Kind::Open,
+            ArbitrageKind::Update => Kind::Update,
+            ArbitrageKind::Close => Kind::Close,
+        };
+
+        Self {
+            kind: kind as i32,
+            instrument: arbitrage.instrument.to_string(),
+            buy_exchange: arbitrage.buy_exchange.to_string(),
+            sell_exchange: arbitrage.sell_exchange.to_string(),
+            buy_price: arbitrage.buy_price.to_f64().unwrap(),
+            buy_price_decimal: arbitrage.buy_price.to_string(),
+            sell_price: arbitrage.sell_price.to_f64().unwrap(),
+            sell_price_decimal: arbitrage.sell_price.to_string(),
+            size: arbitrage.size.to_f64().unwrap(),
+            size_decimal: arbitrage.size.to_string(),
+            profit: arbitrage.profit.to_f64().unwrap(),
+            profit_decimal: arbitrage.profit.to_string(),
+            duration_ms: arbitrage.duration.as_millis() as u64,
+        }
+    }
+}
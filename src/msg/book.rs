@@ -0,0 +1,435 @@
+use std::cmp::Reverse;
+
+use im::OrdMap;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use super::Level;
+use crate::server;
+
+/// One side of a book, best first by the price in the key, then by exchange and
+/// whether the level is synthetic. Clones share their nodes, so handing out a
+/// book doesn't copy its levels.
+pub type Ladder<P> = OrdMap<(P, &'static str, bool), Level>;
+
+/// An instrument's levels merged across exchanges, best first.
+pub struct Book {
+    pub instrument: &'static str,
+    pub bids: Ladder<Reverse<Decimal>>,
+    pub asks: Ladder<Decimal>,
+    /// The same levels keyed by their price after the exchange's taker fee.
+    pub fee_adjusted_bids: Ladder<Reverse<Decimal>>,
+    pub fee_adjusted_asks: Ladder<Decimal>,
+    /// Exchanges left out because their levels went stale.
+    pub excluded_exchanges: Vec<&'static str>,
+}
+
+/// Levels as `(price, level)`, where the price is the one they're ordered by.
+pub type Priced<'a> = Box<dyn Iterator<Item = (Decimal, &'a Level)> + 'a>;
+
+/// How a client wants to see a book.
+pub struct View {
+    /// Levels per side.
+    pub depth: usize,
+    /// Exchanges to keep, all of them when empty.
+    pub exchanges: Vec<String>,
+    /// Merge the levels different exchanges quote at the same price.
+    pub consolidated: bool,
+    /// Groups levels into buckets of this size, bids rounded down and asks rounded up.
+    pub tick_size: Option<Decimal>,
+    /// Use the prices after taker fees rather than the quoted ones.
+    pub fee_adjusted: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Bid,
+    Ask,
+}
+
+/// The amount an exchange contributes to a level, along with the price it
+/// quoted when the level's price was fee-adjusted from a single quote.
+struct Contribution {
+    exchange: &'static str,
+    synthetic: bool,
+    amount: Decimal,
+    raw_price: Option<Decimal>,
+}
+
+/// Whether an allow-list of exchanges, empty to allow all of them, contains `exchange`.
+pub(super) fn includes(exchanges: &[String], exchange: &str) -> bool {
+    exchanges.is_empty()
+        || exchanges
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(exchange))
+}
+
+impl View {
+    fn includes(&self, exchange: &str) -> bool {
+        includes(&self.exchanges, exchange)
+    }
+
+    /// The price rounded to the tick size, or left as it is when rounding it
+    /// overflows.
+    fn group_price(&self, price: Decimal, side: Side) -> Decimal {
+        let tick_size = match self.tick_size {
+            Some(tick_size) => tick_size,
+            None => return price,
+        };
+
+        let ticks = match price.checked_div(tick_size) {
+            Some(ticks) => ticks,
+            None => return price,
+        };
+        let ticks = match side {
+            Side::Bid => ticks.floor(),
+            Side::Ask => ticks.ceil(),
+        };
+        ticks.checked_mul(tick_size).unwrap_or(price)
+    }
+
+    /// Levels as `(price, level)`, where the price is the one to sort and show
+    /// them by, either the quoted or the fee-adjusted one.
+    fn side<'a>(
+        &self,
+        levels: impl Iterator<Item = (Decimal, &'a Level)>,
+        side: Side,
+    ) -> Vec<server::orderbook::Level> {
+        let levels = levels
+            .filter(|(_, level)| self.includes(level.exchange))
+            .map(|(price, level)| (self.group_price(price, side), level));
+
+        // Levels are sorted and rounding keeps them so, levels sharing a price
+        // are next to each other
+        let mut groups: Vec<(Decimal, Vec<Contribution>)> = Vec::new();
+        let mut count = 0;
+        for (price, level) in levels {
+            let raw_price = self.fee_adjusted.then_some(level.price);
+
+            if let Some((group_price, contributions)) = groups.last_mut() {
+                if *group_price == price {
+                    match contributions.iter_mut().find(|contribution| {
+                        contribution.exchange == level.exchange
+                            && contribution.synthetic == level.synthetic
+                    }) {
+                        Some(contribution) => {
+                            contribution.amount += level.amount;
+                            if contribution.raw_price != raw_price {
+                                contribution.raw_price = None;
+                            }
+                        }
+                        None => {
+                            contributions.push(Contribution {
+                                exchange: level.exchange,
+                                synthetic: level.synthetic,
+                                amount: level.amount,
+                                raw_price,
+                            });
+                            if !self.consolidated {
+                                count += 1;
+                            }
+                        }
+                    }
+                    continue;
+                }
+            }
+            if count >= self.depth {
+                break;
+            }
+            groups.push((
+                price,
+                vec![Contribution {
+                    exchange: level.exchange,
+                    synthetic: level.synthetic,
+                    amount: level.amount,
+                    raw_price,
+                }],
+            ));
+            count += 1;
+        }
+
+        groups
+            .into_iter()
+            .flat_map(|(price, contributions)| {
+                if self.consolidated {
+                    vec![consolidate(price, &contributions)]
+                } else {
+                    contributions
+                        .iter()
+                        .map(|contribution| {
+                            let mut level: server::orderbook::Level = (&Level {
+                                exchange: contribution.exchange,
+                                price,
+                                amount: contribution.amount,
+                                synthetic: contribution.synthetic,
+                            })
+                                .into();
+                            set_raw_price(&mut level, contribution.raw_price);
+                            level
+                        })
+                        .collect()
+                }
+            })
+            .take(self.depth)
+            .collect()
+    }
+}
+
+fn set_raw_price(level: &mut server::orderbook::Level, raw_price: Option<Decimal>) {
+    if let Some(raw_price) = raw_price {
+        level.raw_price = raw_price.to_f64().unwrap();
+        level.raw_price_decimal = raw_price.to_string();
+    }
+}
+
+/// A single level for all exchanges quoting the same price.
+fn consolidate(price: Decimal, contributions: &[Contribution]) -> server::orderbook::Level {
+    let amount: Decimal = contributions
+        .iter()
+        .map(|contribution| contribution.amount)
+        .sum();
+
+    server::orderbook::Level {
+        exchange: String::new(),
+        price: price.to_f64().unwrap(),
+        amount: amount.to_f64().unwrap(),
+        price_decimal: price.to_string(),
+        amount_decimal: amount.to_string(),
+        contributions: contributions
+            .iter()
+            .map(|contribution| server::orderbook::Contribution {
+                exchange: contribution.exchange.to_string(),
+                synthetic: contribution.synthetic,
+                amount: contribution.amount.to_f64().unwrap(),
+                amount_decimal: contribution.amount.to_string(),
+                raw_price: contribution
+                    .raw_price
+                    .map_or(0.0, |raw_price| raw_price.to_f64().unwrap()),
+                raw_price_decimal: contribution
+                    .raw_price
+                    .map_or_else(String::new, |raw_price| raw_price.to_string()),
+            })
+            .collect(),
+        raw_price: 0.0,
+        raw_price_decimal: String::new(),
+        synthetic: false,
+    }
+}
+
+impl Book {
+    /// Bids from the highest price down.
+    pub fn bids(&self) -> impl Iterator<Item = &Level> {
+        self.bids.values()
+    }
+
+    /// Asks from the lowest price up.
+    pub fn asks(&self) -> impl Iterator<Item = &Level> {
+        self.asks.values()
+    }
+
+    /// Bids from the highest price after fees down, paired with that price.
+    pub fn fee_adjusted_bids(&self) -> impl Iterator<Item = (Decimal, &Level)> {
+        self.fee_adjusted_bids
+            .iter()
+            .map(|((Reverse(price), _, _), level)| (*price, level))
+    }
+
+    /// Asks from the lowest price after fees up, paired with that price.
+    pub fn fee_adjusted_asks(&self) -> impl Iterator<Item = (Decimal, &Level)> {
+        self.fee_adjusted_asks
+            .iter()
+            .map(|((price, _, _), level)| (*price, level))
+    }
+
+    /// Bids as `(price, level)` by the quoted or the fee-adjusted price.
+    pub fn priced_bids(&self, fee_adjusted: bool) -> Priced<'_> {
+        if fee_adjusted {
+            Box::new(self.fee_adjusted_bids())
+        } else {
+            Box::new(self.bids().map(|level| (level.price, level)))
+        }
+    }
+
+    /// Asks as `(price, level)` by the quoted or the fee-adjusted price.
+    pub fn priced_asks(&self, fee_adjusted: bool) -> Priced<'_> {
+        if fee_adjusted {
+            Box::new(self.fee_adjusted_asks())
+        } else {
+            Box::new(self.asks().map(|level| (level.price, level)))
+        }
+    }
+
+    pub fn summary(&self, view: &View) -> server::orderbook::Summary {
+        let best_bid = self
+            .priced_bids(view.fee_adjusted)
+            .find(|(_, bid)| view.includes(bid.exchange));
+        let best_ask = self
+            .priced_asks(view.fee_adjusted)
+            .find(|(_, ask)| view.includes(ask.exchange));
+
+        let spread = if let (Some((best_bid, _)), Some((best_ask, _))) = (best_bid, best_ask) {
+            best_ask - best_bid
+        } else {
+            Decimal::ZERO
+        };
+
+        server::orderbook::Summary {
+            instrument: self.instrument.to_string(),
+            spread: spread.to_f64().unwrap(),
+            spread_decimal: spread.to_string(),
+            bids: view.side(self.priced_bids(view.fee_adjusted), Side::Bid),
+            asks: view.side(self.priced_asks(view.fee_adjusted), Side::Ask),
+            excluded_exchanges: self
+                .excluded_exchanges
+                .iter()
+                .filter(|exchange| view.includes(exchange))
+                .map(|exchange| exchange.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn level(exchange: &'static str, price: &str, amount: &str) -> Level {
+        Level {
+            exchange,
+            price: dec(price),
+            amount: dec(amount),
+            synthetic: false,
+        }
+    }
+
+    /// A book of bids only, their fee-adjusted prices one below the quoted ones.
+    fn book(bids: &[Level]) -> Book {
+        Book {
+            instrument: "BTC/USDT",
+            bids: bids
+                .iter()
+                .map(|bid| ((Reverse(bid.price), bid.exchange, bid.synthetic), *bid))
+                .collect(),
+            asks: Ladder::new(),
+            fee_adjusted_bids: bids
+                .iter()
+                .map(|bid| {
+                    let price = bid.price - Decimal::ONE;
+                    ((Reverse(price), bid.exchange, bid.synthetic), *bid)
+                })
+                .collect(),
+            fee_adjusted_asks: Ladder::new(),
+            excluded_exchanges: Vec::new(),
+        }
+    }
+
+    fn view(depth: usize, consolidated: bool, tick_size: &str) -> View {
+        View {
+            depth,
+            exchanges: Vec::new(),
+            consolidated,
+            tick_size: Some(dec(tick_size)),
+            fee_adjusted: false,
+        }
+    }
+
+    /// `(exchange, price, amount)` of every level of a side.
+    fn rows(levels: &[server::orderbook::Level]) -> Vec<(&str, &str, &str)> {
+        levels
+            .iter()
+            .map(|level| {
+                (
+                    level.exchange.as_str(),
+                    level.price_decimal.as_str(),
+                    level.amount_decimal.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bids_round_down_and_asks_up() {
+        let view = view(10, false, "0.5");
+
+        assert_eq!(view.group_price(dec("100.5"), Side::Bid), dec("100.5"));
+        assert_eq!(view.group_price(dec("100.7"), Side::Bid), dec("100.5"));
+        assert_eq!(view.group_price(dec("100.5"), Side::Ask), dec("100.5"));
+        assert_eq!(view.group_price(dec("100.2"), Side::Ask), dec("100.5"));
+    }
+
+    #[test]
+    fn grouped_levels_merge_per_exchange_and_count_toward_depth() {
+        let book = book(&[
+            level("binance", "100.9", "1"),
+            level("kraken", "100.8", "1"),
+            level("binance", "100.6", "2"),
+            level("binance", "99.9", "1"),
+        ]);
+
+        let summary = book.summary(&view(2, false, "1"));
+        assert_eq!(
+            rows(&summary.bids),
+            [("binance", "100", "3"), ("kraken", "100", "1")]
+        );
+
+        let summary = book.summary(&view(3, false, "1"));
+        assert_eq!(
+            rows(&summary.bids),
+            [
+                ("binance", "100", "3"),
+                ("kraken", "100", "1"),
+                ("binance", "99", "1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn consolidated_levels_list_their_contributions() {
+        let book = book(&[
+            level("binance", "100.9", "1"),
+            level("kraken", "100.8", "1"),
+            level("binance", "100.6", "2"),
+            level("binance", "99.9", "1"),
+        ]);
+
+        let summary = book.summary(&view(1, true, "1"));
+
+        assert_eq!(rows(&summary.bids), [("", "100", "4")]);
+        let contributions: Vec<_> = summary.bids[0]
+            .contributions
+            .iter()
+            .map(|contribution| {
+                (
+                    contribution.exchange.as_str(),
+                    contribution.amount_decimal.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(contributions, [("binance", "3"), ("kraken", "1")]);
+    }
+
+    #[test]
+    fn raw_price_is_cleared_when_contributions_quote_different_prices() {
+        let book = book(&[
+            level("binance", "100.9", "1"),
+            level("binance", "100.6", "2"),
+            level("kraken", "100.8", "1"),
+        ]);
+        let view = View {
+            fee_adjusted: true,
+            ..view(10, false, "1")
+        };
+
+        let summary = book.summary(&view);
+
+        let raw_prices: Vec<_> = summary
+            .bids
+            .iter()
+            .map(|level| (level.exchange.as_str(), level.raw_price_decimal.as_str()))
+            .collect();
+        assert_eq!(raw_prices, [("binance", ""), ("kraken", "100.8")]);
+    }
+}
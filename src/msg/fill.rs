@@ -0,0 +1,281 @@
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use super::{book::includes, Book, Level};
+use crate::server;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FillSide {
+    /// Takes the asks.
+    Buy,
+    /// Takes the bids.
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Quantity {
+    Base(Decimal),
+    Quote(Decimal),
+}
+
+/// What taking a quantity from the book would cost, or earn when selling.
+pub struct Fill {
+    pub instrument: &'static str,
+    pub side: FillSide,
+    /// Base amount filled.
+    pub quantity: Decimal,
+    /// Quote amount paid or received.
+    pub notional: Decimal,
+    /// `false` when the book doesn't hold the whole quantity.
+    pub complete: bool,
+    /// Price of the last level taken from.
+    pub worst_price: Option<Decimal>,
+    pub mid: Option<Decimal>,
+    /// How much is taken from each exchange, in the order they are first reached.
+    pub allocations: Vec<Allocation>,
+}
+
+pub struct Allocation {
+    pub exchange: &'static str,
+    pub synthetic: bool,
+    pub quantity: Decimal,
+    pub notional: Decimal,
+}
+
+impl Book {
+    /// Walks the asks when buying or the bids when selling, best first, until
+    /// `quantity` is filled, using only the given exchanges or all of them
+    /// when `exchanges` is empty.
+    pub fn fill(&self, side: FillSide, quantity: Quantity, exchanges: &[String]) -> Fill {
+        let levels: Box<dyn Iterator<Item = &Level>> = match side {
+            FillSide::Buy => Box::new(self.asks()),
+            FillSide::Sell => Box::new(self.bids()),
+        };
+
+        let best_bid = self.bids().find(|bid| includes(exchanges, bid.exchange));
+        let best_ask = self.asks().find(|ask| includes(exchanges, ask.exchange));
+        let mid = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => Some((bid.price + ask.price) / Decimal::TWO),
+            _ => None,
+        };
+
+        let mut fill = Fill {
+            instrument: self.instrument,
+            side,
+            quantity: Decimal::ZERO,
+            notional: Decimal::ZERO,
+            complete: false,
+            worst_price: None,
+            mid,
+            allocations: Vec::new(),
+        };
+
+        let mut remaining = match quantity {
+            Quantity::Base(amount) | Quantity::Quote(amount) => amount,
+        };
+
+        for level in levels.filter(|level| includes(exchanges, level.exchange)) {
+            if remaining <= Decimal::ZERO {
+                break;
+            }
+
+            let (amount, notional) = match quantity {
+                Quantity::Base(_) => {
+                    let amount = level.amount.min(remaining);
+                    remaining -= amount;
+                    (amount, amount * level.price)
+                }
+                Quantity::Quote(_) => {
+                    let notional = (level.amount * level.price).min(remaining);
+                    remaining -= notional;
+                    (notional / level.price, notional)
+                }
+            };
+
+            fill.quantity += amount;
+            fill.notional += notional;
+            fill.worst_price = Some(level.price);
+
+            match fill.allocations.iter_mut().find(|allocation| {
+                allocation.exchange == level.exchange && allocation.synthetic == level.synthetic
+            }) {
+                Some(allocation) => {
+                    allocation.quantity += amount;
+                    allocation.notional += notional;
+                }
+                None => fill.allocations.push(Allocation {
+                    exchange: level.exchange,
+                    synthetic: level.synthetic,
+                    quantity: amount,
+                    notional,
+                }),
+            }
+        }
+
+        fill.complete = remaining <= Decimal::ZERO;
+        fill
+    }
+}
+
+impl Fill {
+    /// Volume-weighted average price of the fill.
+    pub fn vwap(&self) -> Option<Decimal> {
+        if self.quantity.is_zero() {
+            return None;
+        }
+        Some(self.notional / self.quantity)
+    }
+
+    /// How much worse than the mid the average price is, in basis points.
+    pub fn slippage_bps(&self) -> Option<Decimal> {
+        let (vwap, mid) = (self.vwap()?, self.mid?);
+        let slippage = match self.side {
+            FillSide::Buy => vwap - mid,
+            FillSide::Sell => mid - vwap,
+        };
+        Some(slippage / mid * Decimal::from(10_000))
+    }
+}
+
+fn decimal_fields(value: Option<Decimal>) -> (f64, String) {
+    match value {
+        Some(value) => (value.to_f64().unwrap(), value.to_string()),
+        None => (0.0, String::new()),
+    }
+}
+
+impl From<&Fill> for server::orderbook::FillResponse {
+    fn from(fill: &Fill) -> Self {
+        let (vwap, vwap_decimal) = decimal_fields(fill.vwap());
+        let (worst_price, worst_price_decimal) = decimal_fields(fill.worst_price);
+        let (mid, mid_decimal) = decimal_fields(fill.mid);
+        let (slippage_bps, slippage_bps_decimal) = decimal_fields(fill.slippage_bps());
+
+        Self {
+            instrument: fill.instrument.to_string(),
+            quantity: fill.quantity.to_f64().unwrap(),
+            quantity_decimal: fill.quantity.to_string(),
+            notional: fill.notional.to_f64().unwrap(),
+            notional_decimal: fill.notional.to_string(),
+            complete: fill.complete,
+            vwap,
+            vwap_decimal,
+            worst_price,
+            worst_price_decimal,
+            mid,
+            mid_decimal,
+            slippage_bps,
+            slippage_bps_decimal,
+            allocations: fill
+                .allocations
+                .iter()
+                .map(|allocation| server::orderbook::Allocation {
+                    exchange: allocation.exchange.to_string(),
+                    synthetic: allocation.synthetic,
+                    quantity: allocation.quantity.to_f64().unwrap(),
+                    quantity_decimal: allocation.quantity.to_string(),
+                    notional: allocation.notional.to_f64().unwrap(),
+                    notional_decimal: allocation.notional.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Reverse;
+
+    use super::*;
+
+    fn level(exchange: &'static str, price: i64, amount: i64) -> Level {
+        Level {
+            exchange,
+            price: Decimal::from(price),
+            amount: Decimal::from(amount),
+            synthetic: false,
+        }
+    }
+
+    fn book(bids: &[Level], asks: &[Level]) -> Book {
+        Book {
+            instrument: "BTC/USDT",
+            bids: bids
+                .iter()
+                .map(|bid| ((Reverse(bid.price), bid.exchange, bid.synthetic), *bid))
+                .collect(),
+            asks: asks
+                .iter()
+                .map(|ask| ((ask.price, ask.exchange, ask.synthetic), *ask))
+                .collect(),
+            fee_adjusted_bids: Default::default(),
+            fee_adjusted_asks: Default::default(),
+            excluded_exchanges: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn quote_fill_ends_partway_through_a_level() {
+        let book = book(
+            &[level("binance", 90, 1)],
+            &[level("binance", 100, 1), level("kraken", 200, 2)],
+        );
+
+        let fill = book.fill(FillSide::Buy, Quantity::Quote(Decimal::from(300)), &[]);
+
+        assert!(fill.complete);
+        assert_eq!(fill.quantity, Decimal::from(2));
+        assert_eq!(fill.notional, Decimal::from(300));
+        assert_eq!(fill.worst_price, Some(Decimal::from(200)));
+        assert_eq!(fill.vwap(), Some(Decimal::from(150)));
+        assert_eq!(fill.mid, Some(Decimal::from(95)));
+    }
+
+    #[test]
+    fn incomplete_fill_takes_the_whole_side() {
+        let book = book(
+            &[level("binance", 100, 1), level("kraken", 99, 2)],
+            &[level("binance", 101, 1)],
+        );
+
+        let fill = book.fill(FillSide::Sell, Quantity::Base(Decimal::from(5)), &[]);
+
+        assert!(!fill.complete);
+        assert_eq!(fill.quantity, Decimal::from(3));
+        assert_eq!(fill.notional, Decimal::from(298));
+        assert_eq!(fill.worst_price, Some(Decimal::from(99)));
+    }
+
+    #[test]
+    fn allocations_sum_an_exchange_across_levels() {
+        let book = book(
+            &[level("binance", 99, 1)],
+            &[
+                level("binance", 100, 1),
+                level("kraken", 101, 1),
+                level("binance", 102, 1),
+                level("okx", 103, 1),
+            ],
+        );
+
+        let fill = book.fill(FillSide::Buy, Quantity::Base(Decimal::from(3)), &[]);
+
+        let allocations: Vec<_> = fill
+            .allocations
+            .iter()
+            .map(|allocation| {
+                (
+                    allocation.exchange,
+                    allocation.quantity,
+                    allocation.notional,
+                )
+            })
+            .collect();
+        assert_eq!(
+            allocations,
+            [
+                ("binance", Decimal::from(2), Decimal::from(202)),
+                ("kraken", Decimal::from(1), Decimal::from(101)),
+            ]
+        );
+    }
+}
@@ -1,18 +1,26 @@
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
 use crate::server;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Level {
     pub exchange: &'static str,
-    pub price: f64,
-    pub amount: f64,
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// Implied from two other books of the exchange rather than quoted directly.
+    pub synthetic: bool,
 }
 
 impl From<&Level> for server::orderbook::Level {
     fn from(level: &Level) -> Self {
         Self {
             exchange: level.exchange.to_string(),
-            price: level.price,
-            amount: level.amount,
+            price: level.price.to_f64().unwrap(),
+            amount: level.amount.to_f64().unwrap(),
+            price_decimal: level.price.to_string(),
+            amount_decimal: level.amount.to_string(),
+            synthetic: level.synthetic,
+            ..Default::default()
         }
     }
 }
@@ -1,7 +1,33 @@
 use super::Level;
 
+/// An exchange's levels for an instrument. Each side is best first, bids from
+/// the highest price down and asks from the lowest up, with no price repeated.
 pub struct Levels {
     pub exchange: &'static str,
+    pub instrument: &'static str,
     pub bids: Vec<Level>,
     pub asks: Vec<Level>,
 }
+
+impl Levels {
+    /// Levels carrying no bids or asks, clearing whatever the exchange published
+    /// before for the instrument.
+    pub fn empty(exchange: &'static str, instrument: &'static str) -> Levels {
+        Levels {
+            exchange,
+            instrument,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Whether both sides are best first with unique prices.
+    pub fn is_ordered(&self) -> bool {
+        self.bids.windows(2).all(|w| w[0].price > w[1].price)
+            && self.asks.windows(2).all(|w| w[0].price < w[1].price)
+    }
+}
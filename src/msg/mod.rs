@@ -1,5 +1,17 @@
+mod arbitrage;
+pub use arbitrage::{Arbitrage, ArbitrageKind};
+
+mod book;
+pub use book::{Book, Ladder, View};
+
+mod fill;
+pub use fill::{FillSide, Quantity};
+
 mod level;
 pub use level::Level;
 
 mod levels;
 pub use levels::Levels;
+
+mod update;
+pub use update::Update;
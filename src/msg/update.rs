@@ -0,0 +1,13 @@
+use super::Levels;
+
+/// What a connector tells the orderbook.
+pub enum Update {
+    /// The exchange's latest levels for an instrument.
+    Levels(Levels),
+    /// The exchange is still streaming, even though its books may not have
+    /// changed since it last sent them.
+    Alive {
+        exchange: &'static str,
+        instruments: &'static [String],
+    },
+}
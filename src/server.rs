@@ -2,15 +2,23 @@ pub mod orderbook {
     tonic::include_proto!("orderbook");
 }
 
-use tokio::sync::{broadcast, mpsc};
+use std::{sync::Arc, time::Duration};
+
+use rust_decimal::Decimal;
+
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::{self, Instant, MissedTickBehavior},
+};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
-use crate::{shutdown, SETTINGS};
+use crate::{market_data::BookCache, msg, shutdown, SETTINGS};
 
 use self::orderbook::{
+    fill_request::{Side, Unit},
     orderbook_aggregator_server::{OrderbookAggregator, OrderbookAggregatorServer},
-    Empty, Summary,
+    ArbitrageEvent, ArbitrageRequest, FillRequest, FillResponse, Summary, SummaryRequest,
 };
 
 pub struct Server {
@@ -18,10 +26,19 @@ pub struct Server {
 }
 
 impl Server {
-    pub async fn serve(&mut self, summary_tx: broadcast::Sender<Summary>) {
+    pub async fn serve(
+        &mut self,
+        book_tx: broadcast::Sender<Arc<msg::Book>>,
+        cache: BookCache,
+        arbitrage_tx: broadcast::Sender<msg::Arbitrage>,
+    ) {
         let addr = SETTINGS.server.address.parse().unwrap();
 
-        let service = OrderbookService { summary_tx };
+        let service = OrderbookService {
+            book_tx,
+            cache,
+            arbitrage_tx,
+        };
         if let Err(err) = tonic::transport::Server::builder()
             .add_service(OrderbookAggregatorServer::new(service))
             .serve_with_shutdown(addr, self.shutdown_rx.recv())
@@ -34,32 +51,230 @@ impl Server {
 }
 
 struct OrderbookService {
-    summary_tx: broadcast::Sender<Summary>,
+    book_tx: broadcast::Sender<Arc<msg::Book>>,
+    cache: BookCache,
+    arbitrage_tx: broadcast::Sender<msg::Arbitrage>,
+}
+
+/// Instruments some connector streams, Deribit's being named after its own instruments.
+fn is_known_instrument(instrument: &str) -> bool {
+    SETTINGS.app.instruments.iter().any(|i| i == instrument)
+        || SETTINGS.deribit.instruments.iter().any(|i| i == instrument)
+        || SETTINGS.synthetic.contains_key(instrument)
+        || SETTINGS
+            .conversion
+            .values()
+            .any(|conversion| conversion.rate_instrument.as_deref() == Some(instrument))
+}
+
+/// The instrument a summary request asks for. Requests predating the field
+/// leave it empty, which stands for the only instrument when there's one.
+fn summary_instrument(request: &SummaryRequest) -> String {
+    match (
+        request.instrument.as_str(),
+        SETTINGS.app.instruments.as_slice(),
+    ) {
+        ("", [instrument]) => instrument.clone(),
+        (instrument, _) => instrument.to_string(),
+    }
+}
+
+/// Decimal places a tick size may have. Finer ticks group nothing, and prices
+/// divided by them can overflow `Decimal`.
+const MAX_TICK_SIZE_SCALE: u32 = 12;
+
+/// The view a request asks for, or why it can't be served.
+fn summary_view(request: &SummaryRequest) -> Result<msg::View, String> {
+    if !is_known_instrument(&request.instrument) {
+        return Err(format!("Unknown instrument: {:?}", request.instrument));
+    }
+
+    let tick_size = match request.tick_size.as_str() {
+        "" => None,
+        tick_size => match tick_size.parse::<Decimal>() {
+            Ok(tick_size)
+                if tick_size > Decimal::ZERO
+                    && tick_size.normalize().scale() <= MAX_TICK_SIZE_SCALE =>
+            {
+                Some(tick_size)
+            }
+            _ => return Err(format!("Invalid tick size: {:?}", tick_size)),
+        },
+    };
+
+    Ok(msg::View {
+        depth: match request.depth {
+            0 => SETTINGS.app.summary_size,
+            depth => depth as usize,
+        },
+        exchanges: request.exchanges.clone(),
+        consolidated: request.consolidated,
+        tick_size,
+        fee_adjusted: request.fee_adjusted,
+    })
+}
+
+/// When summaries go out to a BookSummary subscriber.
+struct Publish {
+    min_interval: Duration,
+    on_change: bool,
+    cadence: Option<Duration>,
+}
+
+/// The policy a request asks for, the configured one when it doesn't.
+fn publish_policy(request: &SummaryRequest) -> Publish {
+    let (min_interval_ms, on_change, cadence_ms) = match &request.publish {
+        Some(policy) => (policy.min_interval_ms, policy.on_change, policy.cadence_ms),
+        None => (
+            SETTINGS.publish.min_interval_ms,
+            SETTINGS.publish.on_change,
+            SETTINGS.publish.cadence_ms,
+        ),
+    };
+
+    Publish {
+        min_interval: Duration::from_millis(min_interval_ms),
+        on_change,
+        cadence: (cadence_ms > 0).then(|| Duration::from_millis(cadence_ms)),
+    }
 }
 
 #[tonic::async_trait]
 impl OrderbookAggregator for OrderbookService {
     type BookSummaryStream = ReceiverStream<Result<Summary, Status>>;
+    type ArbitrageStream = ReceiverStream<Result<ArbitrageEvent, Status>>;
 
     async fn book_summary(
         &self,
-        _: Request<Empty>,
+        request: Request<SummaryRequest>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
+        let mut request = request.into_inner();
+        request.instrument = summary_instrument(&request);
+        let view = summary_view(&request).map_err(Status::invalid_argument)?;
+        let publish = publish_policy(&request);
+
+        let (tx, rx) = mpsc::channel(SETTINGS.app.channel_capacity);
+
+        // Subscribe before reading the cache so no update falls in between
+        let mut book_rx = self.book_tx.subscribe();
+        let cached = self.cache.get(&request.instrument);
+
+        tokio::spawn(async move {
+            // The newest book, its summary waiting to go out while `pending`.
+            // Summaries are only made when due, books in between are conflated
+            let mut latest = cached;
+            let mut pending = latest.is_some();
+            let mut sent: Option<Summary> = None;
+            // Sending again any earlier breaks the minimum interval
+            let mut earliest = Instant::now();
+
+            let mut cadence = publish.cadence.map(|period| {
+                let mut cadence = time::interval(period);
+                cadence.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                cadence
+            });
+
+            loop {
+                let due = tokio::select! {
+                    msg = book_rx.recv() => match msg {
+                        Ok(book) => {
+                            if book.instrument != request.instrument {
+                                continue;
+                            }
+                            latest = Some(book);
+                            pending = true;
+                            cadence.is_none() && Instant::now() >= earliest
+                        }
+                        Err(err) => {
+                            if let broadcast::error::RecvError::Lagged(_) = err {
+                                eprintln!("Summary channel lagged: {}", err);
+                                continue;
+                            }
+                            break;
+                        }
+                    },
+                    // The latest summary goes out on every tick, new or not
+                    _ = async { cadence.as_mut().unwrap().tick().await }, if cadence.is_some() => {
+                        latest.is_some()
+                    },
+                    _ = time::sleep_until(earliest), if cadence.is_none() && pending => true,
+                };
+
+                if !due {
+                    continue;
+                }
+                pending = false;
+
+                let summary = match &latest {
+                    Some(book) => book.summary(&view),
+                    None => continue,
+                };
+                if publish.on_change && sent.as_ref() == Some(&summary) {
+                    continue;
+                }
+
+                if let Err(err) = tx.send(Ok(summary.clone())).await {
+                    eprintln!("book_summary rpc closed: {}", err);
+                    break;
+                }
+                sent = Some(summary);
+                earliest = Instant::now() + publish.min_interval;
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_summary(
+        &self,
+        request: Request<SummaryRequest>,
+    ) -> Result<Response<Summary>, Status> {
+        let mut request = request.into_inner();
+        request.instrument = summary_instrument(&request);
+        let view = summary_view(&request).map_err(Status::invalid_argument)?;
+
+        match self.cache.get(&request.instrument) {
+            Some(book) => Ok(Response::new(book.summary(&view))),
+            None => Err(Status::unavailable(format!(
+                "No book for {} yet",
+                request.instrument
+            ))),
+        }
+    }
+
+    async fn arbitrage(
+        &self,
+        request: Request<ArbitrageRequest>,
+    ) -> Result<Response<Self::ArbitrageStream>, Status> {
+        let request = request.into_inner();
+        if !request.instrument.is_empty() && !is_known_instrument(&request.instrument) {
+            return Err(Status::invalid_argument(format!(
+                "Unknown instrument: {:?}",
+                request.instrument
+            )));
+        }
+
         let (tx, rx) = mpsc::channel(SETTINGS.app.channel_capacity);
 
-        let mut summary_rx = self.summary_tx.subscribe();
+        let mut arbitrage_rx = self.arbitrage_tx.subscribe();
         tokio::spawn(async move {
             loop {
-                match summary_rx.recv().await {
-                    Ok(summary) => {
-                        if let Err(err) = tx.send(Ok(summary)).await {
-                            eprintln!("book_summary rpc closed: {}", err);
+                match arbitrage_rx.recv().await {
+                    Ok(arbitrage) => {
+                        if !request.instrument.is_empty()
+                            && arbitrage.instrument != request.instrument
+                        {
+                            continue;
+                        }
+
+                        if let Err(err) = tx.send(Ok((&arbitrage).into())).await {
+                            eprintln!("arbitrage rpc closed: {}", err);
                             break;
                         }
                     }
                     Err(err) => {
                         if let broadcast::error::RecvError::Lagged(_) = err {
-                            eprintln!("Summary channel lagged: {}", err);
+                            eprintln!("Arbitrage channel lagged: {}", err);
                         } else {
                             break;
                         };
@@ -70,4 +285,47 @@ impl OrderbookAggregator for OrderbookService {
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    async fn cost_to_fill(
+        &self,
+        request: Request<FillRequest>,
+    ) -> Result<Response<FillResponse>, Status> {
+        let request = request.into_inner();
+        if !is_known_instrument(&request.instrument) {
+            return Err(Status::invalid_argument(format!(
+                "Unknown instrument: {:?}",
+                request.instrument
+            )));
+        }
+
+        let amount = match request.quantity.parse::<Decimal>() {
+            Ok(amount) if amount > Decimal::ZERO => amount,
+            _ => {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid quantity: {:?}",
+                    request.quantity
+                )))
+            }
+        };
+
+        let side = match request.side() {
+            Side::Buy => msg::FillSide::Buy,
+            Side::Sell => msg::FillSide::Sell,
+        };
+        let quantity = match request.unit() {
+            Unit::Base => msg::Quantity::Base(amount),
+            Unit::Quote => msg::Quantity::Quote(amount),
+        };
+
+        match self.cache.get(&request.instrument) {
+            Some(book) => {
+                let fill = book.fill(side, quantity, &request.exchanges);
+                Ok(Response::new((&fill).into()))
+            }
+            None => Err(Status::unavailable(format!(
+                "No book for {} yet",
+                request.instrument
+            ))),
+        }
+    }
 }
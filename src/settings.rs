@@ -1,22 +1,150 @@
+use std::collections::HashMap;
+
 use config::{Config, ConfigError};
+use rust_decimal::Decimal;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct App {
     pub summary_size: usize,
     pub channel_capacity: usize,
+    pub max_level_age_ms: u64,
+    pub exchanges: Vec<String>,
+    /// Pairs written as `BASE/QUOTE`, each connector maps them to its own symbols.
+    pub instruments: Vec<String>,
+}
+
+/// How a venue's book is streamed.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BookMode {
+    /// The venue resends the top of its book on every message.
+    Partial,
+    /// A local book is built from a REST snapshot and kept up to date with diffs.
+    Diff,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Binance {
-    pub currency_pair: String,
+    pub mode: BookMode,
     pub depth: usize,
     pub latency: String,
+    pub snapshot_limit: usize,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Bitstamp {
-    pub currency_pair: String,
+    pub mode: BookMode,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Kraken {
+    pub depth: usize,
+    /// Keyed by instrument.
+    pub precision: HashMap<String, Precision>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct Precision {
+    pub price: usize,
+    pub qty: usize,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Coinbase {
+    pub max_heartbeat_gap_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Bybit {
+    pub depth: usize,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Htx {
+    pub depth: usize,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Deribit {
+    /// Deribit instrument names, streamed as instruments of their own.
+    pub instruments: Vec<String>,
+    pub interval: String,
+    pub heartbeat_interval: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Reconnect {
+    pub min_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Heartbeat {
+    pub ping_interval_ms: u64,
+    pub idle_timeout_ms: u64,
+    /// Idle timeouts overriding `idle_timeout_ms`, keyed by exchange name.
+    #[serde(default)]
+    pub venue_idle_timeout_ms: HashMap<String, u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Fees {
+    /// Taker fees in basis points, keyed by exchange name.
+    #[serde(default)]
+    pub taker_bps: HashMap<String, Decimal>,
+}
+
+impl Fees {
+    /// An exchange's taker fee as a fraction, zero when none is configured.
+    pub fn taker_fee(&self, exchange: &str) -> Decimal {
+        let bps = self
+            .taker_bps
+            .get(&exchange.to_lowercase())
+            .copied()
+            .unwrap_or_default();
+        bps / Decimal::from(10_000)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Arbitrage {
+    pub fee_adjusted: bool,
+}
+
+/// How a venue quoting some instruments in another currency, such as USD
+/// instead of USDT, has its prices converted into the configured instruments'.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Conversion {
+    /// Instruments the venue streams in place of configured ones, keyed by the
+    /// configured one.
+    pub instruments: HashMap<String, String>,
+    /// Book whose mid converts between the two quote currencies, streamed by
+    /// the venue alongside its other instruments.
+    pub rate_instrument: Option<String>,
+    /// Multiplies the venue's prices when there's no `rate_instrument`.
+    pub fixed_rate: Option<Decimal>,
+    /// Decimal places converted prices are rounded to.
+    pub price_precision: u32,
+}
+
+/// An instrument implied from two legs quoted in the same currency, such as
+/// ETH/BTC from ETH/USDT and BTC/USDT.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Synthetic {
+    /// The leg pricing the synthetic's base currency.
+    pub base: String,
+    /// The leg pricing the synthetic's quote currency.
+    pub quote: String,
+}
+
+/// When summaries go out to a BookSummary subscriber.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Publish {
+    pub min_interval_ms: u64,
+    pub on_change: bool,
+    /// Zero to publish on every book instead.
+    pub cadence_ms: u64,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -29,7 +157,26 @@ pub struct Settings {
     pub app: App,
     pub binance: Binance,
     pub bitstamp: Bitstamp,
+    pub kraken: Kraken,
+    pub coinbase: Coinbase,
+    pub bybit: Bybit,
+    pub htx: Htx,
+    pub deribit: Deribit,
+    pub reconnect: Reconnect,
+    pub heartbeat: Heartbeat,
+    pub fees: Fees,
+    pub arbitrage: Arbitrage,
+    /// Keyed by the synthetic instrument.
+    #[serde(default)]
+    pub synthetic: HashMap<String, Synthetic>,
+    /// Keyed by the venue's lowercase name.
+    #[serde(default)]
+    pub conversion: HashMap<String, Conversion>,
+    pub publish: Publish,
     pub server: Server,
+    /// Instruments of the venues with a conversion, keyed by lowercase name.
+    #[serde(skip)]
+    venue_instruments: HashMap<String, Vec<String>>,
 }
 
 const CONFIG_FILE_PATH: &str = "./Settings.toml";
@@ -41,6 +188,41 @@ impl Settings {
             .build()
             .unwrap();
 
-        s.try_deserialize::<Self>()
+        let mut settings = s.try_deserialize::<Self>()?;
+
+        settings.venue_instruments = settings
+            .conversion
+            .iter()
+            .map(|(venue, conversion)| {
+                let mut instruments: Vec<String> = settings
+                    .app
+                    .instruments
+                    .iter()
+                    .map(|instrument| {
+                        conversion
+                            .instruments
+                            .get(instrument)
+                            .unwrap_or(instrument)
+                            .clone()
+                    })
+                    .collect();
+                if let Some(rate_instrument) = &conversion.rate_instrument {
+                    if !instruments.contains(rate_instrument) {
+                        instruments.push(rate_instrument.clone());
+                    }
+                }
+                (venue.clone(), instruments)
+            })
+            .collect();
+
+        Ok(settings)
+    }
+
+    /// Instruments a venue streams, the configured ones unless it quotes some
+    /// in another currency.
+    pub fn instruments(&self, exchange: &str) -> &[String] {
+        self.venue_instruments
+            .get(&exchange.to_lowercase())
+            .unwrap_or(&self.app.instruments)
     }
 }